@@ -0,0 +1,290 @@
+use crate::paging::{self, PagingError};
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+pub mod bump;
+pub mod fixed_size_block;
+pub mod linked_list;
+
+#[cfg(any(
+    all(feature = "bump-allocator", feature = "linked-list-allocator"),
+    all(feature = "bump-allocator", feature = "fixed-size-block-allocator"),
+    all(
+        feature = "linked-list-allocator",
+        feature = "fixed-size-block-allocator"
+    ),
+))]
+compile_error!("only one heap allocator feature can be enabled at a time");
+
+#[cfg(not(any(
+    feature = "bump-allocator",
+    feature = "linked-list-allocator",
+    feature = "fixed-size-block-allocator",
+)))]
+compile_error!("a heap allocator feature must be enabled");
+
+#[cfg(feature = "bump-allocator")]
+type Backend = bump::BumpAllocator;
+#[cfg(feature = "linked-list-allocator")]
+type Backend = linked_list::LinkedListAllocator;
+#[cfg(feature = "fixed-size-block-allocator")]
+type Backend = fixed_size_block::FixedSizeBlockAllocator;
+
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// Initial size of the heap, it grows on demand up to the heap limit.
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Default for the heap limit.
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+/// The heap grows by at least this many bytes at once.
+const HEAP_GROWTH_STEP: usize = 64 * 1024;
+
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+#[global_allocator]
+static ALLOCATOR: Locked<Backend> = Locked::new(Backend::new());
+
+/// The interface shared by the heap allocator backends.
+pub trait HeapAllocator {
+    /// Hands the given heap region to the allocator, discarding any previous
+    /// state.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the given heap bounds are valid and that
+    /// the heap is unused.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    /// Returns a pointer to memory fitting `layout`, or null if the heap is
+    /// exhausted.
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`HeapAllocator::allocate`] on this
+    /// allocator with the same `layout`.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Adds `size` bytes directly after the end of the heap region.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the added memory is mapped and unused.
+    unsafe fn extend(&mut self, size: usize);
+
+    /// Bytes currently handed out, including padding added by the allocator.
+    fn used(&self) -> usize;
+
+    /// Total size of the heap region.
+    fn size(&self) -> usize;
+}
+
+/// A wrapper around spin::Mutex to permit trait implementations.
+pub struct Locked<A> {
+    inner: spin::Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> spin::MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+// The lock is only held with interrupts disabled, so that a preempted thread
+// can never hold it while another one allocates.
+unsafe impl<A: HeapAllocator> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (ptr, growth) = interrupts::without_interrupts(|| {
+            let mut heap = self.lock();
+            let ptr = heap.allocate(layout);
+            if !ptr.is_null() {
+                return (ptr, None);
+            }
+            let growth = grow(&mut *heap, layout);
+            if growth.is_enough() {
+                (heap.allocate(layout), Some(growth))
+            } else {
+                (ptr, Some(growth))
+            }
+        });
+        // logged without the heap lock, since the log sinks may allocate
+        if let Some(growth) = growth {
+            growth.log(layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| self.lock().deallocate(ptr, layout))
+    }
+}
+
+/// Align the given address `addr` upwards to alignment `align`.
+///
+/// Requires that `align` is a power of two.
+pub fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Maps the heap region to newly allocated frames and hands it to the
+/// global allocator. Requires the frame allocator and paging to be set up.
+pub fn init_heap() -> Result<(), PagingError> {
+    map_region(HEAP_START, HEAP_SIZE)?;
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+/// Maps the pages of `start..start + size` to newly allocated frames.
+pub fn map_region(start: usize, size: usize) -> Result<(), PagingError> {
+    let page_range = {
+        let region_start = VirtAddr::new(start as u64);
+        let region_end = region_start + size - 1u64;
+        let region_start_page = Page::containing_address(region_start);
+        let region_end_page = Page::containing_address(region_end);
+        Page::range_inclusive(region_start_page, region_end_page)
+    };
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    for page in page_range {
+        paging::map_new_page(page, flags)?;
+    }
+    Ok(())
+}
+
+/// Sets the size the heap may grow to. Does not shrink the heap if it is
+/// already larger.
+pub fn set_heap_limit(limit: usize) {
+    HEAP_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+pub fn heap_limit() -> usize {
+    HEAP_LIMIT.load(Ordering::Relaxed)
+}
+
+/// The outcome of [`grow`], logged once the heap lock is released.
+enum Growth {
+    /// The heap is not initialized yet or the layout is too large to grow for.
+    Impossible,
+    LimitReached {
+        required: usize,
+        limit: usize,
+    },
+    /// `mapped` bytes were added, fewer than `required` if mapping a page
+    /// failed.
+    Grew {
+        mapped: usize,
+        required: usize,
+        size: usize,
+        error: Option<(Page, PagingError)>,
+    },
+}
+
+impl Growth {
+    /// Whether the heap grew far enough for the allocation.
+    fn is_enough(&self) -> bool {
+        matches!(self, Growth::Grew { mapped, required, .. } if mapped >= required)
+    }
+
+    fn log(&self, layout: Layout) {
+        match self {
+            Growth::Impossible => {}
+            Growth::LimitReached { required, limit } => log::warn!(
+                "cannot grow heap by {} KiB for {:?}, limit of {} KiB reached",
+                required / 1024,
+                layout,
+                limit / 1024
+            ),
+            Growth::Grew {
+                mapped,
+                size,
+                error,
+                ..
+            } => {
+                if let Some((page, err)) = error {
+                    log::warn!("mapping heap page {:?} failed: {:?}", page, err);
+                }
+                if *mapped > 0 {
+                    log::debug!("heap grew by {} KiB to {} KiB", mapped / 1024, size / 1024);
+                }
+            }
+        }
+    }
+}
+
+/// Maps new pages after the end of the heap at [`HEAP_START`] so that an
+/// allocation of `layout` fits, without exceeding the heap limit.
+///
+/// Runs with the heap locked, so it must not log or allocate itself.
+fn grow(heap: &mut impl HeapAllocator, layout: Layout) -> Growth {
+    const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+    let size = heap.size();
+    if size == 0 {
+        // the heap is not initialized yet, so paging might not be either
+        return Growth::Impossible;
+    }
+    let required = match layout.size().checked_add(layout.align()) {
+        Some(required) => align_up(required, PAGE_SIZE),
+        None => return Growth::Impossible,
+    };
+    let limit = heap_limit();
+    if size.saturating_add(required) > limit {
+        return Growth::LimitReached { required, limit };
+    }
+
+    let growth = required.max(HEAP_GROWTH_STEP).min(limit - size);
+    let heap_end = HEAP_START + size;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let mut mapped = 0;
+    let mut error = None;
+    while mapped < growth {
+        let page = Page::containing_address(VirtAddr::new((heap_end + mapped) as u64));
+        if let Err(err) = paging::map_new_page(page, flags) {
+            error = Some((page, err));
+            break;
+        }
+        mapped += PAGE_SIZE;
+    }
+    if mapped > 0 {
+        // the pages were just mapped and lie directly after the heap
+        unsafe { heap.extend(mapped) };
+    }
+    Growth::Grew {
+        mapped,
+        required,
+        size: heap.size(),
+        error,
+    }
+}
+
+/// Bytes of the heap currently handed out and still available.
+///
+/// With the fixed-size-block backend, freed blocks waiting in the per-size
+/// lists count as available, although they only serve allocations of their
+/// own block size.
+pub fn heap_usage() -> (usize, usize) {
+    interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.lock();
+        (heap.used(), heap.size() - heap.used())
+    })
+}
+
+#[test_case]
+fn test_align_up() {
+    assert_eq!(align_up(0, 8), 0);
+    assert_eq!(align_up(1, 8), 8);
+    assert_eq!(align_up(8, 8), 8);
+    assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+}
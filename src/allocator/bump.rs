@@ -0,0 +1,77 @@
+use super::{align_up, HeapAllocator};
+use core::alloc::Layout;
+use core::ptr;
+
+/// Hands out memory by bumping a pointer. Memory is only reclaimed when the
+/// most recent allocation is freed or when all allocations have been freed.
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl BumpAllocator {
+    /// Creates a new empty bump allocator.
+    pub const fn new() -> Self {
+        BumpAllocator {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+}
+
+impl Default for BumpAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapAllocator for BumpAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+        self.allocations = 0;
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => return ptr::null_mut(),
+        };
+
+        if alloc_end > self.heap_end {
+            ptr::null_mut() // out of memory
+        } else {
+            self.next = alloc_end;
+            self.allocations += 1;
+            alloc_start as *mut u8
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        } else if ptr as usize + layout.size() == self.next {
+            // the most recent allocation can be rolled back
+            self.next = ptr as usize;
+        }
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        self.heap_end += size;
+    }
+
+    fn used(&self) -> usize {
+        self.next - self.heap_start
+    }
+
+    fn size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+}
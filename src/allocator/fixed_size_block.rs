@@ -0,0 +1,121 @@
+use super::linked_list::LinkedListAllocator;
+use super::HeapAllocator;
+use core::alloc::Layout;
+use core::{mem, ptr};
+
+/// The block sizes to use.
+///
+/// The sizes must each be power of 2 because they are also used as
+/// the block alignment (alignments must be always powers of 2).
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct ListNode {
+    next: *mut ListNode,
+}
+
+/// Serves small allocations from per-size lists of fixed-size blocks and
+/// falls back to a [`LinkedListAllocator`] for large ones.
+pub struct FixedSizeBlockAllocator {
+    list_heads: [*mut ListNode; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+    /// Bytes handed out, counting small allocations as their full block.
+    /// Blocks in the per-size lists are not handed out and not counted.
+    used: usize,
+}
+
+// the free blocks live in the heap memory owned by the allocator
+unsafe impl Send for FixedSizeBlockAllocator {}
+
+/// Choose an appropriate block size for the given layout.
+///
+/// Returns an index into the `BLOCK_SIZES` array.
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+impl FixedSizeBlockAllocator {
+    /// Creates an empty FixedSizeBlockAllocator.
+    pub const fn new() -> Self {
+        FixedSizeBlockAllocator {
+            list_heads: [ptr::null_mut(); BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+            used: 0,
+        }
+    }
+}
+
+impl Default for FixedSizeBlockAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.list_heads = [ptr::null_mut(); BLOCK_SIZES.len()];
+        self.used = 0;
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let index = match list_index(&layout) {
+            Some(index) => index,
+            None => {
+                let ptr = self.fallback_allocator.allocate(layout);
+                if !ptr.is_null() {
+                    self.used += layout.size();
+                }
+                return ptr;
+            }
+        };
+        let block_size = BLOCK_SIZES[index];
+        let head = self.list_heads[index];
+        let block = if head.is_null() {
+            // no block exists in list => allocate new block
+            // only works if all block sizes are a power of 2
+            let block_align = block_size;
+            let layout = Layout::from_size_align(block_size, block_align).unwrap();
+            self.fallback_allocator.allocate(layout)
+        } else {
+            self.list_heads[index] = unsafe { (*head).next };
+            head as *mut u8
+        };
+        if !block.is_null() {
+            self.used += block_size;
+        }
+        block
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match list_index(&layout) {
+            Some(index) => {
+                // verify that block has size and alignment required for storing node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(ListNode {
+                    next: self.list_heads[index],
+                });
+                self.list_heads[index] = new_node_ptr;
+                self.used -= BLOCK_SIZES[index];
+            }
+            None => {
+                self.used -= layout.size();
+                self.fallback_allocator.deallocate(ptr, layout);
+            }
+        }
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        self.fallback_allocator.extend(size);
+    }
+
+    fn used(&self) -> usize {
+        self.used
+    }
+
+    fn size(&self) -> usize {
+        self.fallback_allocator.size()
+    }
+}
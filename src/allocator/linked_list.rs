@@ -0,0 +1,185 @@
+use super::{align_up, HeapAllocator};
+use core::alloc::Layout;
+use core::{mem, ptr};
+
+struct ListNode {
+    size: usize,
+    next: *mut ListNode,
+}
+
+impl ListNode {
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+/// A first-fit allocator that keeps the free regions in a list sorted by
+/// address and merges adjacent regions when memory is freed.
+pub struct LinkedListAllocator {
+    /// Dummy node whose `next` points to the first free region.
+    head: ListNode,
+    heap_start: usize,
+    size: usize,
+    used: usize,
+}
+
+// the list nodes live in the heap memory owned by the allocator
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    /// Creates an empty LinkedListAllocator.
+    pub const fn new() -> Self {
+        LinkedListAllocator {
+            head: ListNode {
+                size: 0,
+                next: ptr::null_mut(),
+            },
+            heap_start: 0,
+            size: 0,
+            used: 0,
+        }
+    }
+
+    /// Adds the given memory region to the free list, merging it with the
+    /// neighbouring free regions if they are adjacent.
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        // find the last node that starts before the new region
+        let mut prev: *mut ListNode = &mut self.head;
+        while !(*prev).next.is_null() && (*(*prev).next).start_addr() < addr {
+            prev = (*prev).next;
+        }
+
+        let node_ptr = addr as *mut ListNode;
+        node_ptr.write(ListNode {
+            size,
+            next: (*prev).next,
+        });
+        (*prev).next = node_ptr;
+
+        let next = (*node_ptr).next;
+        if !next.is_null() && (*node_ptr).end_addr() == (*next).start_addr() {
+            (*node_ptr).size += (*next).size;
+            (*node_ptr).next = (*next).next;
+        }
+        if !ptr::eq(prev, &self.head) && (*prev).end_addr() == addr {
+            (*prev).size += (*node_ptr).size;
+            (*prev).next = (*node_ptr).next;
+        }
+    }
+
+    /// Looks for a free region that can hold an allocation with the given
+    /// size and alignment, removes it from the list and returns the region
+    /// together with the start address of the allocation.
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(usize, usize, usize)> {
+        let mut prev: *mut ListNode = &mut self.head;
+        unsafe {
+            while !(*prev).next.is_null() {
+                let region = (*prev).next;
+                if let Some(alloc_start) = Self::alloc_from_region(&*region, size, align) {
+                    let region_start = (*region).start_addr();
+                    let region_end = (*region).end_addr();
+                    (*prev).next = (*region).next;
+                    return Some((region_start, region_end, alloc_start));
+                }
+                prev = region;
+            }
+        }
+        None
+    }
+
+    /// Tries to use the given region for an allocation. Any memory left before
+    /// or after the allocation must be large enough to hold a `ListNode`.
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Option<usize> {
+        let min_size = mem::size_of::<ListNode>();
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start != region.start_addr() && alloc_start - region.start_addr() < min_size {
+            alloc_start = align_up(region.start_addr() + min_size, align);
+        }
+        let alloc_end = alloc_start.checked_add(size)?;
+
+        if alloc_end > region.end_addr() {
+            return None;
+        }
+        let excess_size = region.end_addr() - alloc_end;
+        if excess_size > 0 && excess_size < min_size {
+            return None;
+        }
+
+        Some(alloc_start)
+    }
+
+    /// Adjusts the given layout so that the resulting allocated memory
+    /// region is also capable of storing a `ListNode`.
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapAllocator for LinkedListAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.head.next = ptr::null_mut();
+        self.heap_start = heap_start;
+        self.size = heap_size;
+        self.used = 0;
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        match self.find_region(size, align) {
+            Some((region_start, region_end, alloc_start)) => {
+                let alloc_end = alloc_start + size;
+                unsafe {
+                    if alloc_start > region_start {
+                        self.add_free_region(region_start, alloc_start - region_start);
+                    }
+                    if region_end > alloc_end {
+                        self.add_free_region(alloc_end, region_end - alloc_end);
+                    }
+                }
+                self.used += size;
+                alloc_start as *mut u8
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.used -= size;
+        self.add_free_region(ptr as usize, size)
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        let heap_end = self.heap_start + self.size;
+        self.size += size;
+        self.add_free_region(heap_end, size);
+    }
+
+    fn used(&self) -> usize {
+        self.used
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
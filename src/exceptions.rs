@@ -0,0 +1,218 @@
+use crate::page_fault::PageFault;
+use crate::{serial, vga_buffer};
+use core::fmt;
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{InterruptStackFrame, SelectorErrorCode};
+use x86_64::VirtAddr;
+
+pub const DIVIDE_ERROR: u8 = 0;
+pub const DEBUG: u8 = 1;
+pub const NON_MASKABLE_INTERRUPT: u8 = 2;
+pub const BREAKPOINT: u8 = 3;
+pub const OVERFLOW: u8 = 4;
+pub const BOUND_RANGE_EXCEEDED: u8 = 5;
+pub const INVALID_OPCODE: u8 = 6;
+pub const DEVICE_NOT_AVAILABLE: u8 = 7;
+pub const DOUBLE_FAULT: u8 = 8;
+pub const INVALID_TSS: u8 = 10;
+pub const SEGMENT_NOT_PRESENT: u8 = 11;
+pub const STACK_SEGMENT_FAULT: u8 = 12;
+pub const GENERAL_PROTECTION_FAULT: u8 = 13;
+pub const PAGE_FAULT: u8 = 14;
+pub const X87_FLOATING_POINT: u8 = 16;
+pub const ALIGNMENT_CHECK: u8 = 17;
+pub const MACHINE_CHECK: u8 = 18;
+pub const SIMD_FLOATING_POINT: u8 = 19;
+pub const VIRTUALIZATION: u8 = 20;
+pub const VMM_COMMUNICATION_EXCEPTION: u8 = 29;
+pub const SECURITY_EXCEPTION: u8 = 30;
+
+pub fn name(vector: u8) -> &'static str {
+    match vector {
+        DIVIDE_ERROR => "DIVIDE ERROR",
+        DEBUG => "DEBUG",
+        NON_MASKABLE_INTERRUPT => "NON-MASKABLE INTERRUPT",
+        BREAKPOINT => "BREAKPOINT",
+        OVERFLOW => "OVERFLOW",
+        BOUND_RANGE_EXCEEDED => "BOUND RANGE EXCEEDED",
+        INVALID_OPCODE => "INVALID OPCODE",
+        DEVICE_NOT_AVAILABLE => "DEVICE NOT AVAILABLE",
+        DOUBLE_FAULT => "DOUBLE FAULT",
+        INVALID_TSS => "INVALID TSS",
+        SEGMENT_NOT_PRESENT => "SEGMENT NOT PRESENT",
+        STACK_SEGMENT_FAULT => "STACK SEGMENT FAULT",
+        GENERAL_PROTECTION_FAULT => "GENERAL PROTECTION FAULT",
+        PAGE_FAULT => "PAGE FAULT",
+        X87_FLOATING_POINT => "X87 FLOATING POINT",
+        ALIGNMENT_CHECK => "ALIGNMENT CHECK",
+        MACHINE_CHECK => "MACHINE CHECK",
+        SIMD_FLOATING_POINT => "SIMD FLOATING POINT",
+        VIRTUALIZATION => "VIRTUALIZATION",
+        VMM_COMMUNICATION_EXCEPTION => "VMM COMMUNICATION EXCEPTION",
+        SECURITY_EXCEPTION => "SECURITY EXCEPTION",
+        _ => "RESERVED",
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorCode {
+    None,
+    Raw(u64),
+    /// Segment selector error code of #TS, #NP, #SS and #GP.
+    Selector(u64),
+    PageFault(PageFault),
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorCode::None => write!(f, "none"),
+            ErrorCode::Raw(code) => write!(f, "{:#x}", code),
+            ErrorCode::Selector(code) => match SelectorErrorCode::new(code) {
+                Some(selector) if selector.is_null() => write!(f, "{:#x} (no selector)", code),
+                Some(selector) => write!(
+                    f,
+                    "{:#x} (table: {:?}, index: {}, external: {})",
+                    code,
+                    selector.descriptor_table(),
+                    selector.index(),
+                    selector.external()
+                ),
+                None => write!(f, "{:#x} (reserved bits set)", code),
+            },
+            ErrorCode::PageFault(fault) => write!(f, "\n{}", fault),
+        }
+    }
+}
+
+/// The diagnostic report printed for every CPU exception.
+pub struct ExceptionReport<'a> {
+    pub vector: u8,
+    pub error_code: ErrorCode,
+    pub stack_frame: &'a InterruptStackFrame,
+}
+
+impl fmt::Display for ExceptionReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "EXCEPTION: {} (vector {})",
+            name(self.vector),
+            self.vector
+        )?;
+        writeln!(f, "Error Code: {}", self.error_code)?;
+        writeln!(f, "{:#?}", self.stack_frame)?;
+        let (cr3_frame, cr3_flags) = Cr3::read();
+        writeln!(f, "CR0: {:?}", Cr0::read())?;
+        writeln!(f, "CR2: {:#x}", Cr2::read().as_u64())?;
+        writeln!(
+            f,
+            "CR3: {:#x} {:?}",
+            cr3_frame.start_address().as_u64(),
+            cr3_flags
+        )?;
+        write!(f, "CR4: {:?}", Cr4::read())
+    }
+}
+
+static DROPPED_REPORTS: AtomicU64 = AtomicU64::new(0);
+
+/// Prints the report for an exception to the VGA buffer and the serial port.
+///
+/// Traps such as NMI and breakpoints are not masked by disabling interrupts,
+/// so they can arrive while the console or the port is locked. Execution
+/// continues after them, so the lock is not taken from its holder; the report
+/// is dropped there instead and counted in [`dropped_reports`].
+pub fn report(vector: u8, error_code: ErrorCode, stack_frame: &InterruptStackFrame) {
+    if !print_report(vector, error_code, stack_frame, false) {
+        DROPPED_REPORTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The number of reports [`report`] could not print completely because the
+/// console or the serial port was locked.
+pub fn dropped_reports() -> u64 {
+    DROPPED_REPORTS.load(Ordering::Relaxed)
+}
+
+/// Reports an exception the kernel cannot recover from and panics.
+///
+/// The report is printed even if the exception interrupted a print, since the
+/// interrupted code never unlocks the console again.
+pub fn fatal(vector: u8, error_code: ErrorCode, stack_frame: &InterruptStackFrame) -> ! {
+    print_report(vector, error_code, stack_frame, true);
+    panic!("EXCEPTION: {}", name(vector));
+}
+
+/// Prints the report to the VGA buffer and the serial port, taking their
+/// locks from the holder if `force` is set. Returns whether both printed it.
+fn print_report(
+    vector: u8,
+    error_code: ErrorCode,
+    stack_frame: &InterruptStackFrame,
+    force: bool,
+) -> bool {
+    let report = ExceptionReport {
+        vector,
+        error_code,
+        stack_frame,
+    };
+    if force {
+        vga_buffer::force_print(format_args!("{}\n", report));
+        serial::force_print(format_args!("{}\n", report));
+        return true;
+    }
+    let printed_vga = vga_buffer::try_print(format_args!("{}\n", report));
+    let printed_serial = serial::try_print(format_args!("{}\n", report));
+    printed_vga && printed_serial
+}
+
+const NO_VECTOR: u16 = u16::MAX;
+
+static EXPECTED_VECTOR: AtomicU16 = AtomicU16::new(NO_VECTOR);
+static CAUGHT_VECTOR: AtomicU16 = AtomicU16::new(NO_VECTOR);
+static RESUME_ADDRESS: AtomicU64 = AtomicU64::new(0);
+
+/// Arms the handler of `vector` to resume execution at an address instead of
+/// reporting the exception, so tests can trigger exceptions deliberately.
+///
+/// Returns the location the resume address must be stored at before the
+/// exception is triggered.
+pub fn expect(vector: u8) -> *mut u64 {
+    CAUGHT_VECTOR.store(NO_VECTOR, Ordering::SeqCst);
+    EXPECTED_VECTOR.store(u16::from(vector), Ordering::SeqCst);
+    RESUME_ADDRESS.as_ptr()
+}
+
+/// The vector caught since the last call to [`expect`], if any.
+pub fn caught() -> Option<u8> {
+    match CAUGHT_VECTOR.load(Ordering::SeqCst) {
+        NO_VECTOR => None,
+        vector => Some(vector as u8),
+    }
+}
+
+/// Redirects the interrupted code to the resume address if `vector` was
+/// expected. Returns whether the exception was handled this way.
+pub(crate) fn try_resume(vector: u8, stack_frame: &mut InterruptStackFrame) -> bool {
+    if EXPECTED_VECTOR
+        .compare_exchange(
+            u16::from(vector),
+            NO_VECTOR,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        )
+        .is_err()
+    {
+        return false;
+    }
+    CAUGHT_VECTOR.store(u16::from(vector), Ordering::SeqCst);
+    let resume = VirtAddr::new(RESUME_ADDRESS.load(Ordering::SeqCst));
+    unsafe {
+        stack_frame
+            .as_mut()
+            .update(|frame| frame.instruction_pointer = resume);
+    }
+    true
+}
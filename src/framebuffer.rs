@@ -0,0 +1,407 @@
+//! A linear framebuffer with 32-bit pixels, drawing primitives and a text
+//! console that `print!` writes to once [`init`] switched to graphics mode.
+//!
+//! Graphics mode has this single console. Everything printed to the virtual
+//! consoles and logged to the screen goes to it, while these VGA text mode
+//! features are not available:
+//!
+//! - switching virtual consoles with Alt+F1 to Alt+F6
+//! - scrollback with Page Up and Page Down
+//! - the status bar
+//! - the hardware cursor and its shapes
+
+use crate::paging::{self, PagingError};
+use crate::sync::IrqSpinLock;
+use crate::vga_buffer::Color;
+use core::fmt;
+use core::ops::Range;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+mod console;
+pub mod font;
+pub mod vbe;
+
+pub use console::FramebufferConsole;
+use font::GLYPH_WIDTH;
+
+/// Start of the virtual region the framebuffer of the adapter is mapped to.
+const FRAMEBUFFER_START: u64 = 0x_7777_0000_0000;
+
+/// The mode the kernel switches to, which gives the text console 100
+/// columns and 75 rows.
+pub const DEFAULT_WIDTH: usize = 800;
+pub const DEFAULT_HEIGHT: usize = 600;
+
+static CONSOLE: IrqSpinLock<Option<FramebufferConsole<'static>>> = IrqSpinLock::new(None);
+
+#[derive(Debug)]
+pub enum FramebufferError {
+    AlreadyInitialized,
+    Vbe(vbe::VbeError),
+    Paging(PagingError),
+}
+
+impl From<vbe::VbeError> for FramebufferError {
+    fn from(err: vbe::VbeError) -> Self {
+        FramebufferError::Vbe(err)
+    }
+}
+
+impl From<PagingError> for FramebufferError {
+    fn from(err: PagingError) -> Self {
+        FramebufferError::Paging(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(0xff, 0xff, 0xff);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    /// The value of a pixel in the framebuffer, blue in the low byte.
+    fn to_pixel(self) -> u32 {
+        u32::from(self.r) << 16 | u32::from(self.g) << 8 | u32::from(self.b)
+    }
+
+    fn from_pixel(pixel: u32) -> Rgb {
+        Rgb::new((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8)
+    }
+}
+
+impl From<Color> for Rgb {
+    /// The color the VGA palette shows for a text mode color.
+    fn from(color: Color) -> Rgb {
+        const PALETTE: [Rgb; 16] = [
+            Rgb::new(0x00, 0x00, 0x00),
+            Rgb::new(0x00, 0x00, 0xaa),
+            Rgb::new(0x00, 0xaa, 0x00),
+            Rgb::new(0x00, 0xaa, 0xaa),
+            Rgb::new(0xaa, 0x00, 0x00),
+            Rgb::new(0xaa, 0x00, 0xaa),
+            Rgb::new(0xaa, 0x55, 0x00),
+            Rgb::new(0xaa, 0xaa, 0xaa),
+            Rgb::new(0x55, 0x55, 0x55),
+            Rgb::new(0x55, 0x55, 0xff),
+            Rgb::new(0x55, 0xff, 0x55),
+            Rgb::new(0x55, 0xff, 0xff),
+            Rgb::new(0xff, 0x55, 0x55),
+            Rgb::new(0xff, 0x55, 0xff),
+            Rgb::new(0xff, 0xff, 0x55),
+            Rgb::new(0xff, 0xff, 0xff),
+        ];
+        PALETTE[color as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// Pixels stored line by line, `stride` pixels apart.
+pub struct Framebuffer<'a> {
+    pixels: &'a mut [u32],
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl<'a> Framebuffer<'a> {
+    /// Panics if `pixels` is too short for `height` lines of `stride`
+    /// pixels.
+    pub fn new(pixels: &'a mut [u32], width: usize, height: usize, stride: usize) -> Self {
+        assert!(width <= stride && pixels.len() >= stride * height);
+        Framebuffer {
+            pixels,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The color of a pixel, `None` outside the framebuffer.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(Rgb::from_pixel(self.pixels[y * self.stride + x]))
+    }
+
+    /// Sets a pixel. Pixels outside the framebuffer are ignored, as they are
+    /// by all drawing functions.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.stride + x] = color.to_pixel();
+        }
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
+        self.fill_rect(Rect::new(0, 0, self.width, self.height), color);
+    }
+
+    /// The columns and lines of `rect` that are inside the framebuffer.
+    fn clip(&self, rect: Rect) -> (Range<usize>, Range<usize>) {
+        let right = rect.x.saturating_add(rect.width).min(self.width);
+        let bottom = rect.y.saturating_add(rect.height).min(self.height);
+        (rect.x.min(right)..right, rect.y.min(bottom)..bottom)
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Rgb) {
+        let (cols, lines) = self.clip(rect);
+        let pixel = color.to_pixel();
+        for y in lines {
+            let start = y * self.stride;
+            self.pixels[start + cols.start..start + cols.end].fill(pixel);
+        }
+    }
+
+    /// Draws the one pixel wide border of `rect`.
+    pub fn draw_rect(&mut self, rect: Rect, color: Rgb) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let right = rect.x + rect.width - 1;
+        let bottom = rect.y + rect.height - 1;
+        self.fill_rect(Rect::new(rect.x, rect.y, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, bottom, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, rect.y, 1, rect.height), color);
+        self.fill_rect(Rect::new(right, rect.y, 1, rect.height), color);
+    }
+
+    /// Draws a line including both end points with Bresenham's algorithm.
+    /// The points may lie outside the framebuffer.
+    pub fn draw_line(&mut self, from: (i32, i32), to: (i32, i32), color: Rgb) {
+        let (mut x, mut y) = from;
+        let dx = (to.0 - x).abs();
+        let dy = -(to.1 - y).abs();
+        let step_x = if x < to.0 { 1 } else { -1 };
+        let step_y = if y < to.1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            if let (Ok(px), Ok(py)) = (usize::try_from(x), usize::try_from(y)) {
+                self.set_pixel(px, py, color);
+            }
+            if (x, y) == to {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Copies an image of `width` pixels per line to `(x, y)`, cutting off
+    /// what lies outside the framebuffer.
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, image: &[Rgb]) {
+        if width == 0 {
+            return;
+        }
+        let height = image.len() / width;
+        let (cols, lines) = self.clip(Rect::new(x, y, width, height));
+        for (line, source) in lines.zip(image.chunks_exact(width)) {
+            let start = line * self.stride;
+            let target = &mut self.pixels[start + cols.start..start + cols.end];
+            for (pixel, &color) in target.iter_mut().zip(source) {
+                *pixel = color.to_pixel();
+            }
+        }
+    }
+
+    /// Draws a character of the built-in font with its top left corner at
+    /// `(x, y)`.
+    pub fn draw_char(&mut self, x: usize, y: usize, c: char, text: Rgb, background: Rgb) {
+        let glyph = font::glyph(c);
+        for (row, &bits) in glyph.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                let color = if font::is_set(bits, col) {
+                    text
+                } else {
+                    background
+                };
+                self.set_pixel(x + col, y + row, color);
+            }
+        }
+    }
+
+    /// Draws a string on one line, without interpreting control characters.
+    pub fn draw_text(&mut self, x: usize, y: usize, s: &str, text: Rgb, background: Rgb) {
+        for (i, c) in s.chars().enumerate() {
+            self.draw_char(x + i * GLYPH_WIDTH, y, c, text, background);
+        }
+    }
+
+    /// Moves the content up by `lines` lines of pixels and fills the lines
+    /// that become free at the bottom.
+    pub fn scroll_up(&mut self, lines: usize, fill: Rgb) {
+        let lines = lines.min(self.height);
+        let end = self.height * self.stride;
+        self.pixels.copy_within(lines * self.stride..end, 0);
+        let free = Rect::new(0, self.height - lines, self.width, lines);
+        self.fill_rect(free, fill);
+    }
+}
+
+/// Switches the adapter to a `width`×`height` graphics mode and makes
+/// `print!` write to a text console on it. Requires paging to be set up.
+/// The VGA text consoles keep their contents but are no longer shown.
+pub fn init(width: usize, height: usize) -> Result<(), FramebufferError> {
+    if is_enabled() {
+        return Err(FramebufferError::AlreadyInitialized);
+    }
+    let adapter = vbe::Adapter::detect()?;
+    adapter.check_mode(width, height)?;
+    let pixels = map(&adapter, width * height)?;
+    adapter.set_mode(width, height)?;
+
+    let framebuffer = Framebuffer::new(pixels, width, height, width);
+    let console = FramebufferConsole::new(framebuffer);
+    *CONSOLE.lock() = Some(console);
+    Ok(())
+}
+
+/// Maps `count` pixels of the framebuffer of the adapter.
+fn map(adapter: &vbe::Adapter, count: usize) -> Result<&'static mut [u32], PagingError> {
+    let size = (count * core::mem::size_of::<u32>()) as u64;
+    let start = Page::<Size4KiB>::containing_address(VirtAddr::new(FRAMEBUFFER_START));
+    let end = Page::containing_address(VirtAddr::new(FRAMEBUFFER_START + size - 1));
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(adapter.framebuffer());
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    for (i, page) in Page::range_inclusive(start, end).enumerate() {
+        // the frames are memory of the adapter, not RAM handed out by the
+        // frame allocator, and the region is used by nothing else
+        unsafe { paging::map_page(page, first_frame + i as u64, flags)? };
+    }
+    Ok(unsafe { core::slice::from_raw_parts_mut(FRAMEBUFFER_START as *mut u32, count) })
+}
+
+/// Whether [`init`] switched to graphics mode.
+pub fn is_enabled() -> bool {
+    CONSOLE.lock().is_some()
+}
+
+/// Runs `f` with the framebuffer console locked, `None` if there is none.
+pub fn with_console<R>(f: impl FnOnce(&mut FramebufferConsole<'static>) -> R) -> Option<R> {
+    CONSOLE.lock().as_mut().map(f)
+}
+
+/// Like [`with_console`], but gives up if the lock is held, returning `None`
+/// then, for [`crate::vga_buffer::try_print`].
+pub(crate) fn try_with_console<R>(
+    f: impl FnOnce(&mut FramebufferConsole<'static>) -> R,
+) -> Option<Option<R>> {
+    CONSOLE.try_lock().map(|mut console| console.as_mut().map(f))
+}
+
+/// Like [`with_console`], but takes the lock even if it is held, for
+/// [`crate::vga_buffer::force_print`].
+pub(crate) fn force_with_console<R>(
+    f: impl FnOnce(&mut FramebufferConsole<'static>) -> R,
+) -> Option<R> {
+    let mut console = match CONSOLE.try_lock() {
+        Some(console) => console,
+        None => {
+            unsafe { CONSOLE.force_unlock() };
+            CONSOLE.lock()
+        }
+    };
+    console.as_mut().map(f)
+}
+
+/// Prints to the framebuffer console, returning `false` if there is none.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    with_console(|console| console.write_fmt(args).unwrap()).is_some()
+}
+
+#[cfg(test)]
+fn test_framebuffer(pixels: &mut [u32], width: usize) -> Framebuffer<'_> {
+    let height = pixels.len() / width;
+    Framebuffer::new(pixels, width, height, width)
+}
+
+#[test_case]
+fn test_fill_and_clip() {
+    let mut pixels = [0; 8 * 6];
+    let mut framebuffer = test_framebuffer(&mut pixels, 8);
+    let red = Rgb::new(0xff, 0, 0);
+    framebuffer.fill_rect(Rect::new(6, 4, 10, 10), red);
+    assert_eq!(framebuffer.pixel(5, 4), Some(Rgb::BLACK));
+    assert_eq!(framebuffer.pixel(6, 4), Some(red));
+    assert_eq!(framebuffer.pixel(7, 5), Some(red));
+    assert_eq!(framebuffer.pixel(8, 5), None);
+
+    framebuffer.set_pixel(100, 100, red);
+    framebuffer.draw_rect(Rect::new(0, 0, 3, 3), Rgb::WHITE);
+    assert_eq!(framebuffer.pixel(2, 2), Some(Rgb::WHITE));
+    assert_eq!(framebuffer.pixel(1, 1), Some(Rgb::BLACK));
+    assert_eq!(pixels[2 * 8 + 2], 0x00ff_ffff);
+}
+
+#[test_case]
+fn test_draw_line() {
+    let mut pixels = [0; 8 * 8];
+    let mut framebuffer = test_framebuffer(&mut pixels, 8);
+    framebuffer.draw_line((-2, -2), (9, 9), Rgb::WHITE);
+    assert!((0..8).all(|i| framebuffer.pixel(i, i) == Some(Rgb::WHITE)));
+    framebuffer.draw_line((7, 0), (0, 3), Rgb::WHITE);
+    assert_eq!(framebuffer.pixel(7, 0), Some(Rgb::WHITE));
+    assert_eq!(framebuffer.pixel(0, 3), Some(Rgb::WHITE));
+    let set = pixels.iter().filter(|&&pixel| pixel != 0).count();
+    // 8 on the diagonal, 8 on the second line, which crosses it once
+    assert_eq!(set, 15);
+}
+
+#[test_case]
+fn test_blit_and_scroll() {
+    let mut pixels = [0; 4 * 4];
+    let mut framebuffer = test_framebuffer(&mut pixels, 4);
+    let image = [Rgb::WHITE, Rgb::new(1, 2, 3), Rgb::new(4, 5, 6), Rgb::BLACK];
+    framebuffer.blit(3, 2, 2, &image);
+    assert_eq!(framebuffer.pixel(3, 2), Some(Rgb::WHITE));
+    assert_eq!(framebuffer.pixel(3, 3), Some(Rgb::new(4, 5, 6)));
+
+    framebuffer.scroll_up(2, Rgb::new(9, 9, 9));
+    assert_eq!(framebuffer.pixel(3, 0), Some(Rgb::WHITE));
+    assert_eq!(framebuffer.pixel(3, 1), Some(Rgb::new(4, 5, 6)));
+    assert_eq!(framebuffer.pixel(0, 3), Some(Rgb::new(9, 9, 9)));
+}
@@ -0,0 +1,244 @@
+//! A text console drawn with the built-in font, understanding the same
+//! control characters and escape sequences as the VGA text writer.
+
+use super::font::{GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::{Framebuffer, Rect, Rgb};
+use crate::vga_buffer::ansi::{Action, Erase, Parser};
+use crate::vga_buffer::{Color, ColorWrite, Rendition, TAB_WIDTH};
+use core::fmt;
+use core::ops::Range;
+
+pub struct FramebufferConsole<'a> {
+    framebuffer: Framebuffer<'a>,
+    rows: usize,
+    cols: usize,
+    row: usize,
+    col: usize,
+    rendition: Rendition,
+    parser: Parser,
+    saved_cursor: (usize, usize),
+}
+
+impl<'a> FramebufferConsole<'a> {
+    /// Takes over a framebuffer, clearing it. Pixels right of the last
+    /// column and below the last row are left unused. Panics if the
+    /// framebuffer cannot hold a single character.
+    pub fn new(framebuffer: Framebuffer<'a>) -> Self {
+        assert!(framebuffer.width() >= GLYPH_WIDTH && framebuffer.height() >= GLYPH_HEIGHT);
+        let mut console = FramebufferConsole {
+            rows: framebuffer.height() / GLYPH_HEIGHT,
+            cols: framebuffer.width() / GLYPH_WIDTH,
+            framebuffer,
+            row: 0,
+            col: 0,
+            rendition: Rendition::new(),
+            parser: Parser::new(),
+            saved_cursor: (0, 0),
+        };
+        console.clear_screen();
+        console
+    }
+
+    /// Number of rows and columns of text.
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// The framebuffer, to draw next to or over the text.
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer<'a> {
+        &mut self.framebuffer
+    }
+
+    /// Writes a string, interpreting ANSI escape sequences. Characters the
+    /// font has no glyph for are drawn as a box.
+    pub fn write_string(&mut self, s: &str) {
+        for c in s.chars() {
+            if !c.is_ascii() {
+                self.print_char(c);
+            } else if let Some(action) = self.parser.advance(c as u8) {
+                self.apply(action);
+            }
+        }
+    }
+
+    fn print_char(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.col = 0,
+            '\t' => {
+                let col = self.col.min(self.cols - 1);
+                self.col = ((col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1);
+            }
+            '\x08' => {
+                self.col = self.col.saturating_sub(1);
+                self.clear_cols(self.row, self.col..self.col + 1);
+            }
+            '\x0c' => self.clear_screen(),
+            '\x07' => {}
+            c => {
+                if self.col >= self.cols {
+                    self.new_line();
+                }
+                let (x, y) = (self.col * GLYPH_WIDTH, self.row * GLYPH_HEIGHT);
+                let text = Rgb::from(self.rendition.text_color);
+                let background = Rgb::from(self.rendition.background);
+                self.framebuffer.draw_char(x, y, c, text, background);
+                self.col += 1;
+            }
+        }
+    }
+
+    /// Blanks the screen and moves the cursor to the top left.
+    pub fn clear_screen(&mut self) {
+        self.framebuffer.clear(Rgb::from(self.rendition.background));
+        self.row = 0;
+        self.col = 0;
+    }
+
+    /// Row and column of the cursor.
+    pub fn cursor_position(&self) -> (usize, usize) {
+        (self.row, self.col.min(self.cols - 1))
+    }
+
+    /// The colors used by [`FramebufferConsole::write_string`].
+    pub fn color(&self) -> (Color, Color) {
+        (self.rendition.text_color, self.rendition.background)
+    }
+
+    pub fn set_color(&mut self, text_color: Color, background: Color) {
+        self.rendition.set_color(text_color, background);
+    }
+
+    fn apply(&mut self, action: Action) {
+        // the column is one past the last while a wrap is pending
+        let col = self.col.min(self.cols - 1);
+        match action {
+            Action::Print(byte) => self.print_char(char::from(byte)),
+            Action::Sgr(params) => {
+                for &param in &params {
+                    self.rendition.apply(param);
+                }
+            }
+            Action::CursorUp(n) => self.row = self.row.saturating_sub(n),
+            Action::CursorDown(n) => self.row = (self.row + n).min(self.rows - 1),
+            Action::CursorForward(n) => self.col = (col + n).min(self.cols - 1),
+            Action::CursorBack(n) => self.col = col.saturating_sub(n),
+            Action::CursorColumn(col) => self.col = col.min(self.cols - 1),
+            Action::CursorPosition { row, col } => {
+                self.row = row.min(self.rows - 1);
+                self.col = col.min(self.cols - 1);
+            }
+            Action::EraseInLine(erase) => {
+                let cols = match erase {
+                    Erase::ToEnd => col..self.cols,
+                    Erase::ToStart => 0..col + 1,
+                    Erase::All | Erase::AllAndHistory => 0..self.cols,
+                };
+                self.clear_cols(self.row, cols);
+            }
+            Action::EraseInDisplay(erase) => {
+                let rows = match erase {
+                    Erase::ToEnd => {
+                        self.clear_cols(self.row, col..self.cols);
+                        self.row + 1..self.rows
+                    }
+                    Erase::ToStart => {
+                        self.clear_cols(self.row, 0..col + 1);
+                        0..self.row
+                    }
+                    // there is no history to erase
+                    Erase::All | Erase::AllAndHistory => 0..self.rows,
+                };
+                for row in rows {
+                    self.clear_cols(row, 0..self.cols);
+                }
+            }
+            Action::SaveCursor => self.saved_cursor = (self.row, self.col),
+            Action::RestoreCursor => (self.row, self.col) = self.saved_cursor,
+        }
+    }
+
+    fn new_line(&mut self) {
+        if self.row == self.rows - 1 {
+            self.framebuffer
+                .scroll_up(GLYPH_HEIGHT, Rgb::from(self.rendition.background));
+        } else {
+            self.row += 1;
+        }
+        self.col = 0;
+    }
+
+    fn clear_cols(&mut self, row: usize, cols: Range<usize>) {
+        let rect = Rect::new(
+            cols.start * GLYPH_WIDTH,
+            row * GLYPH_HEIGHT,
+            cols.len() * GLYPH_WIDTH,
+            GLYPH_HEIGHT,
+        );
+        self.framebuffer
+            .fill_rect(rect, Rgb::from(self.rendition.background));
+    }
+}
+
+impl fmt::Write for FramebufferConsole<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+impl ColorWrite for FramebufferConsole<'_> {
+    fn color(&self) -> (Color, Color) {
+        FramebufferConsole::color(self)
+    }
+
+    fn set_color(&mut self, text_color: Color, background: Color) {
+        FramebufferConsole::set_color(self, text_color, background)
+    }
+}
+
+/// Whether a cell shows `c` in `text_color` on the background color.
+#[cfg(test)]
+fn shows(console: &FramebufferConsole, row: usize, col: usize, c: char, text_color: Color) -> bool {
+    use super::font;
+    let glyph = font::glyph(c);
+    (0..GLYPH_HEIGHT).all(|y| {
+        (0..GLYPH_WIDTH).all(|x| {
+            let expected = if font::is_set(glyph[y], x) {
+                text_color
+            } else {
+                console.rendition.background
+            };
+            let pixel = console
+                .framebuffer
+                .pixel(col * GLYPH_WIDTH + x, row * GLYPH_HEIGHT + y);
+            pixel == Some(Rgb::from(expected))
+        })
+    })
+}
+
+#[test_case]
+fn test_console_wraps_and_scrolls() {
+    const COLS: usize = 4;
+    const ROWS: usize = 3;
+    let (width, height) = (COLS * GLYPH_WIDTH, ROWS * GLYPH_HEIGHT);
+    let mut pixels = alloc::vec![0; width * height];
+    let mut console = FramebufferConsole::new(Framebuffer::new(&mut pixels, width, height, width));
+    assert_eq!(console.size(), (ROWS, COLS));
+
+    fmt::Write::write_fmt(&mut console, format_args!("ab\x1b[31mcde\n")).unwrap();
+    assert!(shows(&console, 0, 0, 'a', Color::White));
+    assert!(shows(&console, 0, 2, 'c', Color::Red));
+    assert!(shows(&console, 1, 0, 'e', Color::Red));
+    assert_eq!(console.cursor_position(), (2, 0));
+
+    // the fourth line scrolls the first one off
+    console.write_string("\x1b[0mf\ng");
+    assert!(shows(&console, 0, 0, 'e', Color::Red));
+    assert!(shows(&console, 1, 0, 'f', Color::White));
+    assert!(shows(&console, 2, 0, 'g', Color::White));
+    assert!(shows(&console, 2, 1, ' ', Color::White));
+
+    console.write_string("\x1b[2;1H\x1b[K");
+    assert!(shows(&console, 1, 0, ' ', Color::White));
+}
@@ -0,0 +1,110 @@
+//! Mode setting through the display interface of the Bochs and QEMU
+//! standard VGA adapters.
+
+use crate::pci;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+const INDEX_PORT: u16 = 0x01ce;
+const DATA_PORT: u16 = 0x01cf;
+
+const INDEX_ID: u16 = 0;
+const INDEX_XRES: u16 = 1;
+const INDEX_YRES: u16 = 2;
+const INDEX_BPP: u16 = 3;
+const INDEX_ENABLE: u16 = 4;
+const INDEX_VIRT_WIDTH: u16 = 6;
+
+/// The ID register reads 0xb0c0 plus the interface version. Version 2 is
+/// the first with 32 bits per pixel and the linear framebuffer.
+const MIN_VERSION: u16 = 0xb0c2;
+
+const ENABLED: u16 = 0x01;
+const LINEAR_FRAMEBUFFER: u16 = 0x40;
+
+pub const MAX_WIDTH: usize = 1600;
+pub const MAX_HEIGHT: usize = 1200;
+pub const BITS_PER_PIXEL: u16 = 32;
+
+const PCI_VENDOR: u16 = 0x1234;
+const PCI_DEVICE: u16 = 0x1111;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VbeError {
+    /// There is no adapter with the Bochs display interface.
+    NotPresent,
+    UnsupportedVersion(u16),
+    UnsupportedMode {
+        width: usize,
+        height: usize,
+    },
+    /// The framebuffer BAR of the adapter is not a memory region.
+    NoFramebuffer,
+}
+
+/// An adapter with the Bochs display interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adapter {
+    framebuffer: PhysAddr,
+}
+
+fn read_register(index: u16) -> u16 {
+    unsafe {
+        Port::new(INDEX_PORT).write(index);
+        Port::new(DATA_PORT).read()
+    }
+}
+
+fn write_register(index: u16, value: u16) {
+    unsafe {
+        Port::new(INDEX_PORT).write(index);
+        Port::new(DATA_PORT).write(value);
+    }
+}
+
+impl Adapter {
+    /// Finds the adapter and its framebuffer.
+    pub fn detect() -> Result<Adapter, VbeError> {
+        let version = read_register(INDEX_ID);
+        if version & 0xfff0 != MIN_VERSION & 0xfff0 {
+            return Err(VbeError::NotPresent);
+        }
+        if version < MIN_VERSION {
+            return Err(VbeError::UnsupportedVersion(version));
+        }
+        let device = pci::find_device(PCI_VENDOR, PCI_DEVICE).ok_or(VbeError::NotPresent)?;
+        let framebuffer = device.memory_bar(0).ok_or(VbeError::NoFramebuffer)?;
+        Ok(Adapter {
+            framebuffer: PhysAddr::new(framebuffer),
+        })
+    }
+
+    /// Where the pixels are stored, line by line without padding, with
+    /// 32 bits per pixel.
+    pub fn framebuffer(&self) -> PhysAddr {
+        self.framebuffer
+    }
+
+    /// Checks that the adapter can show a `width`×`height` mode.
+    pub fn check_mode(&self, width: usize, height: usize) -> Result<(), VbeError> {
+        if width == 0 || height == 0 || width > MAX_WIDTH || height > MAX_HEIGHT {
+            return Err(VbeError::UnsupportedMode { width, height });
+        }
+        Ok(())
+    }
+
+    /// Switches to a `width`×`height` mode with 32-bit pixels, leaving text
+    /// mode behind. The framebuffer is cleared to black.
+    pub fn set_mode(&self, width: usize, height: usize) -> Result<(), VbeError> {
+        self.check_mode(width, height)?;
+        // the resolution can only be changed while the display is disabled
+        write_register(INDEX_ENABLE, 0);
+        write_register(INDEX_XRES, width as u16);
+        write_register(INDEX_YRES, height as u16);
+        write_register(INDEX_BPP, BITS_PER_PIXEL);
+        write_register(INDEX_ENABLE, ENABLED | LINEAR_FRAMEBUFFER);
+        // lines are as long as the screen is wide unless changed afterwards
+        write_register(INDEX_VIRT_WIDTH, width as u16);
+        Ok(())
+    }
+}
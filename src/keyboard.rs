@@ -0,0 +1,417 @@
+use crate::console_print;
+use crate::framebuffer;
+use crate::pic::{self, InterruptIndex};
+use crate::vga_buffer::{self, ConsoleId};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use heapless::mpmc::MpMcQueue;
+use pc_keyboard::layouts::{AnyLayout, Azerty, De105Key, Dvorak104Key, Uk105Key, Us104Key};
+use pc_keyboard::{HandleControl, KeyboardLayout, ScancodeSet, ScancodeSet1};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+pub use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+
+const DATA_PORT: u16 = 0x60;
+const SCANCODE_QUEUE_SIZE: usize = 128;
+
+/// Raw scancodes read by the IRQ 1 handler, waiting to be decoded.
+static SCANCODES: MpMcQueue<u8, SCANCODE_QUEUE_SIZE> = MpMcQueue::new();
+/// Scancodes dropped because [`SCANCODES`] was full.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+static DECODER: spin::Mutex<Decoder> = spin::Mutex::new(Decoder::new(Layout::Us104));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us104,
+    Uk105,
+    De105,
+    Azerty,
+    Dvorak104,
+}
+
+impl Layout {
+    const fn to_any(self) -> AnyLayout {
+        match self {
+            Layout::Us104 => AnyLayout::Us104Key(Us104Key),
+            Layout::Uk105 => AnyLayout::Uk105Key(Uk105Key),
+            Layout::De105 => AnyLayout::De105Key(De105Key),
+            Layout::Azerty => AnyLayout::Azerty(Azerty),
+            Layout::Dvorak104 => AnyLayout::Dvorak104Key(Dvorak104Key),
+        }
+    }
+}
+
+/// State of the modifier keys at the time of a key event.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub lshift: bool,
+    pub rshift: bool,
+    pub lctrl: bool,
+    pub rctrl: bool,
+    pub lalt: bool,
+    pub ralt: bool,
+    pub capslock: bool,
+    pub numlock: bool,
+}
+
+impl Modifiers {
+    pub fn is_shifted(&self) -> bool {
+        self.lshift || self.rshift
+    }
+
+    pub fn is_ctrl(&self) -> bool {
+        self.lctrl || self.rctrl
+    }
+
+    pub fn is_alt(&self) -> bool {
+        self.lalt || self.ralt
+    }
+
+    fn to_pc_keyboard(self) -> pc_keyboard::Modifiers {
+        pc_keyboard::Modifiers {
+            lshift: self.lshift,
+            rshift: self.rshift,
+            lctrl: self.lctrl,
+            rctrl: self.rctrl,
+            numlock: self.numlock,
+            capslock: self.capslock,
+            alt_gr: self.ralt,
+            rctrl2: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    /// Modifiers after this event has been applied.
+    pub modifiers: Modifiers,
+    /// What a key press translates to in the current layout, `None` for releases.
+    pub key: Option<DecodedKey>,
+}
+
+/// Turns scancode set 1 bytes into [`KeyEvent`]s, tracking modifier state.
+pub struct Decoder {
+    scancodes: ScancodeSet1,
+    layout: AnyLayout,
+    modifiers: Modifiers,
+}
+
+impl Decoder {
+    pub const fn new(layout: Layout) -> Decoder {
+        Decoder {
+            scancodes: ScancodeSet1::new(),
+            layout: layout.to_any(),
+            modifiers: Modifiers {
+                lshift: false,
+                rshift: false,
+                lctrl: false,
+                rctrl: false,
+                lalt: false,
+                ralt: false,
+                capslock: false,
+                numlock: true,
+            },
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout.to_any();
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// Feeds one byte from the keyboard controller. Returns an event once a
+    /// complete (possibly 0xE0-prefixed) make or break code has been seen.
+    pub fn add_byte(&mut self, scancode: u8) -> Option<KeyEvent> {
+        let event = self.scancodes.advance_state(scancode).ok()??;
+        let down = event.state == KeyState::Down;
+        let m = &mut self.modifiers;
+        match event.code {
+            KeyCode::LShift => m.lshift = down,
+            KeyCode::RShift => m.rshift = down,
+            KeyCode::LControl => m.lctrl = down,
+            KeyCode::RControl => m.rctrl = down,
+            KeyCode::LAlt => m.lalt = down,
+            KeyCode::RAltGr => m.ralt = down,
+            KeyCode::CapsLock if down => m.capslock = !m.capslock,
+            KeyCode::NumpadLock if down => m.numlock = !m.numlock,
+            _ => {}
+        }
+
+        let key = if down {
+            Some(self.layout.map_keycode(
+                event.code,
+                &self.modifiers.to_pc_keyboard(),
+                HandleControl::Ignore,
+            ))
+        } else {
+            None
+        };
+
+        Some(KeyEvent {
+            code: event.code,
+            state: event.state,
+            modifiers: self.modifiers,
+            key,
+        })
+    }
+}
+
+pub fn init() {
+    pic::unmask(InterruptIndex::Keyboard);
+}
+
+pub fn set_layout(layout: Layout) {
+    interrupts::without_interrupts(|| DECODER.lock().set_layout(layout));
+}
+
+/// Called by the IRQ 1 handler: reads the pending scancode and queues it.
+pub(crate) fn handle_interrupt() {
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    let scancode = unsafe { port.read() };
+    add_scancode(scancode);
+}
+
+/// Queues a scancode and wakes the task waiting for it. Must not block or
+/// allocate, since it runs in interrupt context.
+fn add_scancode(scancode: u8) {
+    if SCANCODES.enqueue(scancode).is_ok() {
+        WAKER.wake();
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of scancodes dropped since boot because the queue was full.
+pub fn dropped_scancodes() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Decodes pending scancodes until one completes a key event.
+pub fn read_event() -> Option<KeyEvent> {
+    while let Some(scancode) = SCANCODES.dequeue() {
+        if let Some(event) = DECODER.lock().add_byte(scancode) {
+            return Some(event);
+        }
+    }
+    None
+}
+
+/// Takes pending key events until one decodes to a character.
+pub fn read_char() -> Option<char> {
+    while let Some(event) = read_event() {
+        if let Some(DecodedKey::Unicode(c)) = event.key {
+            return Some(c);
+        }
+    }
+    None
+}
+
+/// The raw scancodes queued by the keyboard interrupt handler.
+///
+/// Only one task can wait on the queue at a time: the waker of the most
+/// recent poll is the one woken.
+pub struct ScancodeStream {
+    /// Value of [`DROPPED`] at the last report.
+    reported_drops: u64,
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        ScancodeStream {
+            reported_drops: dropped_scancodes(),
+        }
+    }
+
+    fn report_drops(&mut self) {
+        let dropped = dropped_scancodes();
+        if dropped != self.reported_drops {
+            log::warn!(
+                "scancode queue full, dropped {} scancodes",
+                dropped - self.reported_drops
+            );
+            self.reported_drops = dropped;
+        }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        self.report_drops();
+        // fast path
+        if let Some(scancode) = SCANCODES.dequeue() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        WAKER.register(cx.waker());
+        // a scancode may have arrived before the waker was registered
+        match SCANCODES.dequeue() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Handles keys that control the consoles instead of producing input.
+/// Returns whether the event was consumed. In graphics mode there is only the
+/// framebuffer console, without scrollback, so no key is consumed.
+fn handle_console_key(event: &KeyEvent) -> bool {
+    if event.state != KeyState::Down || framebuffer::is_enabled() {
+        return false;
+    }
+    let console = match event.code {
+        KeyCode::F1 => 0,
+        KeyCode::F2 => 1,
+        KeyCode::F3 => 2,
+        KeyCode::F4 => 3,
+        KeyCode::F5 => 4,
+        KeyCode::F6 => 5,
+        KeyCode::PageUp => {
+            vga_buffer::with_active_console(|writer| {
+                writer.scroll_up(vga_buffer::SCROLL_PAGE_LINES)
+            });
+            return true;
+        }
+        KeyCode::PageDown => {
+            vga_buffer::with_active_console(|writer| {
+                writer.scroll_down(vga_buffer::SCROLL_PAGE_LINES)
+            });
+            return true;
+        }
+        _ => return false,
+    };
+    match ConsoleId::new(console) {
+        Some(console) if event.modifiers.is_alt() => {
+            vga_buffer::switch_console(console);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Task that decodes the incoming scancodes and prints typed characters to
+/// the shown console. Page Up and Page Down scroll the shown console, Alt+F1
+/// to Alt+F6 switch between the virtual consoles.
+pub async fn print_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    while let Some(scancode) = scancodes.next().await {
+        // the decoder lock is dropped before the console lock is taken
+        let event = DECODER.lock().add_byte(scancode);
+        let event = event.filter(|event| !handle_console_key(event));
+        // typed characters are echoed on the shown console
+        let console = vga_buffer::active_console();
+        match event.and_then(|event| event.key) {
+            Some(DecodedKey::Unicode(character)) => console_print!(console, "{}", character),
+            Some(DecodedKey::RawKey(key)) => console_print!(console, "{:?}", key),
+            None => {}
+        }
+    }
+}
+
+#[test_case]
+fn test_decode_shifted_letter() {
+    let mut decoder = Decoder::new(Layout::Us104);
+    let shift = decoder.add_byte(0x2A).unwrap();
+    assert!(shift.modifiers.lshift);
+    let a = decoder.add_byte(0x1E).unwrap();
+    assert_eq!(a.state, KeyState::Down);
+    assert_eq!(a.key, Some(DecodedKey::Unicode('A')));
+    let release = decoder.add_byte(0x9E).unwrap();
+    assert_eq!(release.state, KeyState::Up);
+    assert_eq!(release.key, None);
+    decoder.add_byte(0xAA);
+    assert!(!decoder.modifiers().is_shifted());
+}
+
+#[test_case]
+fn test_decode_extended_and_locks() {
+    let mut decoder = Decoder::new(Layout::Us104);
+    // 0xE0 prefix alone does not produce an event
+    assert!(decoder.add_byte(0xE0).is_none());
+    let alt = decoder.add_byte(0x38).unwrap();
+    assert_eq!(alt.code, KeyCode::RAltGr);
+    assert!(alt.modifiers.is_alt());
+    decoder.add_byte(0xE0);
+    decoder.add_byte(0xB8);
+    assert!(!decoder.modifiers().is_alt());
+
+    decoder.add_byte(0x3A);
+    assert!(decoder.modifiers().capslock);
+    let a = decoder.add_byte(0x1E).unwrap();
+    assert_eq!(a.key, Some(DecodedKey::Unicode('A')));
+}
+
+#[test_case]
+fn test_alternate_layout() {
+    let mut decoder = Decoder::new(Layout::Azerty);
+    // the key labelled Q on a US keyboard is A on AZERTY
+    let key = decoder.add_byte(0x10).unwrap();
+    assert_eq!(key.key, Some(DecodedKey::Unicode('a')));
+    decoder.set_layout(Layout::Us104);
+    let key = decoder.add_byte(0x10).unwrap();
+    assert_eq!(key.key, Some(DecodedKey::Unicode('q')));
+}
+
+#[test_case]
+fn test_queue_overflow_is_counted() {
+    while SCANCODES.dequeue().is_some() {}
+    let dropped = dropped_scancodes();
+    for _ in 0..SCANCODE_QUEUE_SIZE + 3 {
+        add_scancode(0x1E);
+    }
+    assert_eq!(dropped_scancodes(), dropped + 3);
+
+    let mut stream = ScancodeStream::new();
+    stream.reported_drops = dropped;
+    stream.report_drops();
+    assert_eq!(stream.reported_drops, dropped + 3);
+    while SCANCODES.dequeue().is_some() {}
+}
+
+#[test_case]
+fn test_stream_wakes_waiting_task() {
+    use crate::task::{executor::Executor, Task};
+    use core::sync::atomic::AtomicUsize;
+
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+    while SCANCODES.dequeue().is_some() {}
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let mut scancodes = ScancodeStream::new();
+        for expected in [0x1E, 0x9E, 0x30] {
+            assert_eq!(scancodes.next().await, Some(expected));
+            RECEIVED.fetch_add(1, Ordering::SeqCst);
+        }
+    }));
+    executor.spawn(Task::new(async {
+        // runs after the consumer is waiting, like the interrupt handler
+        for scancode in [0x1E, 0x9E, 0x30] {
+            crate::task::yield_now().await;
+            add_scancode(scancode);
+        }
+    }));
+    executor.run_until_complete();
+    assert_eq!(RECEIVED.load(Ordering::SeqCst), 3);
+}
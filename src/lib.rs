@@ -2,19 +2,50 @@
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+#[cfg(test)]
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 
+pub mod allocator;
+pub mod exceptions;
+pub mod framebuffer;
 pub mod gdt;
 pub mod interrupts;
+pub mod keyboard;
+pub mod logger;
+pub mod memory;
+pub mod page_fault;
+pub mod paging;
+pub mod pci;
+pub mod pic;
 pub mod serial;
+pub mod status_bar;
+pub mod sync;
+pub mod task;
+pub mod thread;
+pub mod timer;
 pub mod vga_buffer;
 
 pub fn init() {
+    logger::init(log::LevelFilter::Info);
     gdt::init();
     interrupts::init_idt();
+    pic::init();
+    timer::init();
+    keyboard::init();
+    x86_64::instructions::interrupts::enable();
+}
+
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
 }
 
 pub trait Testable {
@@ -41,10 +72,11 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
-    serial_println!("[failed]\n");
-    serial_println!("Error: {}\n", info);
+    // the panic may have happened while the serial port was locked
+    serial::force_print(format_args!("[failed]\n\nError: {}\n\n", info));
+    logger::dump_on_panic();
     exit_qemu(QemuExitCode::Failed);
-    loop {}
+    hlt_loop();
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -63,13 +95,22 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
     }
 }
 
+#[cfg(test)]
+entry_point!(test_kernel_main);
+
 /// Entry point for `cargo xtest`
 #[cfg(test)]
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     init();
+    unsafe {
+        memory::init(&boot_info.memory_map);
+        paging::init(x86_64::VirtAddr::new(boot_info.physical_memory_offset));
+    }
+    allocator::init_heap().expect("heap initialization failed");
+    vga_buffer::set_scrollback_size(vga_buffer::DEFAULT_SCROLLBACK_LINES);
+    thread::init();
     test_main();
-    loop {}
+    hlt_loop();
 }
 
 #[cfg(test)]
@@ -77,3 +118,12 @@ pub extern "C" fn _start() -> ! {
 fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    let (used, free) = allocator::heap_usage();
+    panic!(
+        "allocation error: {:?} ({} bytes of heap used, {} bytes free)",
+        layout, used, free
+    )
+}
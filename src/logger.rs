@@ -0,0 +1,254 @@
+//! A [`log`] backend that timestamps messages and writes them to the VGA
+//! console and COM1. Every message is also recorded in an in-memory ring
+//! buffer that can be read back like `dmesg`, whichever sinks are enabled.
+
+use crate::sync::IrqSpinLock;
+use crate::vga_buffer::{self, Color};
+use crate::{serial, timer};
+use core::fmt;
+use core::ops::BitOr;
+use core::sync::atomic::{AtomicU8, Ordering};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+mod dmesg;
+mod ring_buffer;
+
+pub use dmesg::{
+    dmesg, dump_on_panic, LogBuffer, LogEntry, LOG_BUFFER, LOG_BUFFER_ENTRIES, MAX_LINE_LENGTH,
+    PANIC_DUMP_LINES,
+};
+pub use ring_buffer::RingBuffer;
+
+/// Maximum number of per-module filters.
+pub const MAX_FILTERS: usize = 16;
+
+static LOGGER: KernelLogger = KernelLogger;
+static SINKS: AtomicU8 = AtomicU8::new(Sinks::ALL.0);
+static FILTERS: IrqSpinLock<Filters> = IrqSpinLock::new(Filters {
+    default: LevelFilter::Info,
+    modules: heapless::Vec::new(),
+});
+
+/// A set of outputs for log messages. The ring buffer is not one of them, it
+/// records every message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sinks(u8);
+
+impl Sinks {
+    pub const NONE: Sinks = Sinks(0);
+    pub const VGA: Sinks = Sinks(1 << 0);
+    pub const SERIAL: Sinks = Sinks(1 << 1);
+    pub const ALL: Sinks = Sinks(Self::VGA.0 | Self::SERIAL.0);
+
+    pub fn contains(self, other: Sinks) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Sinks {
+    type Output = Sinks;
+
+    fn bitor(self, rhs: Sinks) -> Sinks {
+        Sinks(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct FilterTableFull;
+
+struct Filters {
+    default: LevelFilter,
+    /// Module path prefixes with their levels; the longest match wins.
+    modules: heapless::Vec<(&'static str, LevelFilter), MAX_FILTERS>,
+}
+
+impl Filters {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| is_module_prefix(module, target))
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// Whether `module` is `target` or one of its parent modules.
+fn is_module_prefix(module: &str, target: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= FILTERS.lock().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let sinks = sinks();
+        let message = Message {
+            uptime: timer::uptime(),
+            record,
+        };
+        if sinks.contains(Sinks::SERIAL) {
+            serial::_print(format_args!("{}\n", message));
+        }
+        LOG_BUFFER.lock().record(
+            message.uptime,
+            record.level(),
+            format_args!("{}: {}", record.target(), record.args()),
+        );
+        if sinks.contains(Sinks::VGA) {
+            vga_buffer::with_writer(|writer| {
+                let (text_color, background) = writer.color();
+                let _ = write!(writer, "{} ", Timestamp(message.uptime));
+                writer.set_color(level_color(record.level()), background);
+                let _ = write!(writer, "{:<5}", record.level());
+                writer.set_color(text_color, background);
+                let _ = writeln!(writer, " {}: {}", record.target(), record.args());
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// A log line as written to the serial port and the ring buffer.
+struct Message<'a> {
+    uptime: core::time::Duration,
+    record: &'a Record<'a>,
+}
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:<5} {}: {}",
+            Timestamp(self.uptime),
+            self.record.level(),
+            self.record.target(),
+            self.record.args()
+        )
+    }
+}
+
+struct Timestamp(core::time::Duration);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:>5}.{:06}]", self.0.as_secs(), self.0.subsec_micros())
+    }
+}
+
+pub fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::Red,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::LightGreen,
+        Level::Debug => Color::LightCyan,
+        Level::Trace => Color::DarkGray,
+    }
+}
+
+/// Installs the logger with the given default level. Messages logged before
+/// are dropped.
+pub fn init(default_level: LevelFilter) {
+    // fails only if a logger is installed already, which is then kept
+    let _ = log::set_logger(&LOGGER);
+    set_default_level(default_level);
+}
+
+pub fn set_default_level(level: LevelFilter) {
+    let mut filters = FILTERS.lock();
+    filters.default = level;
+    log::set_max_level(filters.max_level());
+}
+
+/// Sets the level of a module and its submodules, e.g. `"blog_os::thread"`,
+/// overriding the default level.
+pub fn set_module_level(module: &'static str, level: LevelFilter) -> Result<(), FilterTableFull> {
+    let mut filters = FILTERS.lock();
+    match filters.modules.iter_mut().find(|(name, _)| *name == module) {
+        Some(filter) => filter.1 = level,
+        None => filters
+            .modules
+            .push((module, level))
+            .map_err(|_| FilterTableFull)?,
+    }
+    log::set_max_level(filters.max_level());
+    Ok(())
+}
+
+/// Removes the filter of a module, so the default level applies again.
+pub fn clear_module_level(module: &str) {
+    let mut filters = FILTERS.lock();
+    filters.modules.retain(|(name, _)| *name != module);
+    log::set_max_level(filters.max_level());
+}
+
+pub fn set_sinks(sinks: Sinks) {
+    SINKS.store(sinks.0, Ordering::Relaxed);
+}
+
+pub fn sinks() -> Sinks {
+    Sinks(SINKS.load(Ordering::Relaxed))
+}
+
+#[test_case]
+fn test_module_filters() {
+    let filters = Filters {
+        default: LevelFilter::Info,
+        modules: heapless::Vec::from_slice(&[
+            ("blog_os::thread", LevelFilter::Trace),
+            ("blog_os::thread::stack", LevelFilter::Off),
+        ])
+        .unwrap(),
+    };
+    assert_eq!(filters.level_for("blog_os::timer"), LevelFilter::Info);
+    assert_eq!(filters.level_for("blog_os::thread"), LevelFilter::Trace);
+    assert_eq!(
+        filters.level_for("blog_os::thread::scheduler"),
+        LevelFilter::Trace
+    );
+    assert_eq!(
+        filters.level_for("blog_os::thread::stack"),
+        LevelFilter::Off
+    );
+    assert_eq!(filters.level_for("blog_os::threads"), LevelFilter::Info);
+    assert_eq!(filters.max_level(), LevelFilter::Trace);
+}
+
+#[test_case]
+fn test_log_reaches_ring_buffer() {
+    let sinks = sinks();
+    // recorded even with every sink disabled
+    set_sinks(Sinks::NONE);
+    set_module_level("blog_os::logger", LevelFilter::Debug).unwrap();
+    LOG_BUFFER.lock().clear();
+
+    log::debug!("visible {}", 42);
+    log::trace!("filtered");
+
+    let buffer = LOG_BUFFER.lock();
+    assert_eq!(buffer.len(), 1);
+    let entry = buffer.iter().next().unwrap();
+    assert_eq!(entry.level, Level::Debug);
+    assert_eq!(entry.text, "blog_os::logger: visible 42");
+    drop(buffer);
+
+    clear_module_level("blog_os::logger");
+    set_sinks(sinks);
+}
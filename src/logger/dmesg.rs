@@ -0,0 +1,192 @@
+use super::ring_buffer::RingBuffer;
+use super::Timestamp;
+use crate::serial;
+use crate::sync::IrqSpinLock;
+use core::fmt::{self, Write};
+use core::time::Duration;
+use log::Level;
+
+/// Number of log lines kept in memory.
+pub const LOG_BUFFER_ENTRIES: usize = 256;
+/// Longer lines are cut off.
+pub const MAX_LINE_LENGTH: usize = 120;
+/// Number of lines printed by the panic handler.
+pub const PANIC_DUMP_LINES: usize = 32;
+
+/// The most recent log lines, kept even when no sink shows them.
+pub static LOG_BUFFER: IrqSpinLock<LogBuffer<LOG_BUFFER_ENTRIES>> =
+    IrqSpinLock::new(LogBuffer::new());
+
+pub struct LogEntry {
+    /// Counts every line logged to the buffer, including dropped ones.
+    pub seq: u64,
+    pub uptime: Duration,
+    pub level: Level,
+    /// `target: message`, cut off at [`MAX_LINE_LENGTH`] bytes.
+    pub text: heapless::String<MAX_LINE_LENGTH>,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>6} {} {:<5} {}",
+            self.seq,
+            Timestamp(self.uptime),
+            self.level,
+            self.text
+        )
+    }
+}
+
+pub struct LogBuffer<const N: usize> {
+    entries: RingBuffer<LogEntry, N>,
+    next_seq: u64,
+}
+
+impl<const N: usize> LogBuffer<N> {
+    pub const fn new() -> Self {
+        LogBuffer {
+            entries: RingBuffer::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn record(&mut self, uptime: Duration, level: Level, text: fmt::Arguments) {
+        let mut line = Truncating(heapless::String::new());
+        let _ = line.write_fmt(text);
+        self.entries.push(LogEntry {
+            seq: self.next_seq,
+            uptime,
+            level,
+            text: line.0,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Iterates from the oldest to the newest line.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &LogEntry> {
+        self.entries.iter()
+    }
+
+    /// The newest `count` lines, oldest first.
+    pub fn tail(&self, count: usize) -> impl Iterator<Item = &LogEntry> {
+        self.entries.tail(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of lines that were overwritten or cleared.
+    pub fn dropped(&self) -> u64 {
+        self.next_seq - self.entries.len() as u64
+    }
+
+    /// Removes all lines. Sequence numbers keep counting.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<const N: usize> Default for LogBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drops whatever does not fit instead of failing.
+struct Truncating<const N: usize>(heapless::String<N>);
+
+impl<const N: usize> fmt::Write for Truncating<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the newest `count` lines of the log buffer to `out`.
+pub fn dmesg(out: &mut impl Write, count: usize) -> fmt::Result {
+    let buffer = LOG_BUFFER.lock();
+    write_tail(&buffer, out, count)
+}
+
+fn write_tail<const N: usize>(
+    buffer: &LogBuffer<N>,
+    out: &mut impl Write,
+    count: usize,
+) -> fmt::Result {
+    let shown = count.min(buffer.len());
+    let skipped = buffer.dropped() + (buffer.len() - shown) as u64;
+    if skipped > 0 {
+        writeln!(out, "({} earlier lines not shown)", skipped)?;
+    }
+    for entry in buffer.tail(shown) {
+        writeln!(out, "{}", entry)?;
+    }
+    Ok(())
+}
+
+/// Prints the newest [`PANIC_DUMP_LINES`] lines to serial, even if the log
+/// buffer or the serial port is locked by code that never returns.
+pub fn dump_on_panic() {
+    let buffer = match LOG_BUFFER.try_lock() {
+        Some(buffer) => buffer,
+        None => {
+            unsafe { LOG_BUFFER.force_unlock() };
+            LOG_BUFFER.lock()
+        }
+    };
+    serial::force_print(format_args!("--- last kernel log lines ---\n"));
+    let _ = write_tail(&buffer, &mut SerialForce, PANIC_DUMP_LINES);
+}
+
+struct SerialForce;
+
+impl Write for SerialForce {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        serial::force_print(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_log_buffer_keeps_newest_lines() {
+    let mut buffer = LogBuffer::<4>::new();
+    for i in 0..7 {
+        buffer.record(
+            Duration::from_millis(i),
+            Level::Info,
+            format_args!("line {}", i),
+        );
+    }
+    assert_eq!(buffer.len(), 4);
+    assert_eq!(buffer.dropped(), 3);
+    let first = buffer.iter().next().unwrap();
+    assert_eq!(first.seq, 3);
+    assert_eq!(first.text, "line 3");
+
+    let mut out = heapless::String::<256>::new();
+    write_tail(&buffer, &mut out, 2).unwrap();
+    let mut lines = out.lines();
+    assert_eq!(lines.next(), Some("(5 earlier lines not shown)"));
+    assert!(lines.next().unwrap().ends_with("INFO  line 5"));
+    assert!(lines.next().unwrap().starts_with("     6 [    0.006000]"));
+    assert_eq!(lines.next(), None);
+}
+
+#[test_case]
+fn test_long_lines_are_truncated() {
+    let mut buffer = LogBuffer::<1>::new();
+    let long = "x".repeat(MAX_LINE_LENGTH * 2);
+    buffer.record(Duration::ZERO, Level::Warn, format_args!("{}", long));
+    assert_eq!(buffer.iter().next().unwrap().text.len(), MAX_LINE_LENGTH);
+}
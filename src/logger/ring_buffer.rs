@@ -0,0 +1,69 @@
+/// A fixed-capacity queue that drops its oldest item when full.
+pub struct RingBuffer<T, const N: usize> {
+    items: heapless::Deque<T, N>,
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        RingBuffer {
+            items: heapless::Deque::new(),
+        }
+    }
+
+    /// Appends an item, returning the oldest one if it had to make room.
+    pub fn push(&mut self, item: T) -> Option<T> {
+        let dropped = if self.items.is_full() {
+            self.items.pop_front()
+        } else {
+            None
+        };
+        // cannot fail, there is room now
+        let _ = self.items.push_back(item);
+        dropped
+    }
+
+    /// Iterates from the oldest to the newest item.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.items.iter()
+    }
+
+    /// The newest `count` items, oldest first.
+    pub fn tail(&self, count: usize) -> impl Iterator<Item = &T> {
+        self.items.iter().skip(self.len().saturating_sub(count))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+}
+
+impl<T, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test_case]
+fn test_ring_buffer_overwrites_oldest() {
+    let mut buffer = RingBuffer::<u8, 4>::new();
+    assert_eq!(buffer.push(1), None);
+    assert_eq!(buffer.push(2), None);
+    assert!(buffer.iter().eq([1, 2].iter()));
+    for item in 3..=4 {
+        buffer.push(item);
+    }
+    assert_eq!(buffer.push(5), Some(1));
+    assert_eq!(buffer.push(6), Some(2));
+    assert_eq!(buffer.len(), 4);
+    assert!(buffer.iter().eq([3, 4, 5, 6].iter()));
+    assert!(buffer.tail(2).eq([5, 6].iter()));
+    assert!(buffer.tail(10).eq([3, 4, 5, 6].iter()));
+}
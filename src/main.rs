@@ -6,32 +6,68 @@
 #![test_runner(blog_os::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-use blog_os::println;
+use blog_os::task::{executor::Executor, Task};
+use blog_os::{
+    allocator, keyboard, logger, memory, paging, println, status_bar, thread, vga_buffer,
+};
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
+use log::LevelFilter;
+use x86_64::VirtAddr;
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    println!("Hello World{}", "!");
+entry_point!(kernel_main);
 
-    blog_os::init(); // new
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    println!("Hello World{}", "!");
 
+    blog_os::init();
+    // heap growth is logged at debug level
+    logger::set_module_level("blog_os::allocator", LevelFilter::Debug)
+        .expect("too many log filters");
     unsafe {
-        *(0xdeadbeef as *mut u8) = 42;
-    };
+        memory::init(&boot_info.memory_map);
+        paging::init(VirtAddr::new(boot_info.physical_memory_offset));
+    }
+    allocator::init_heap().expect("heap initialization failed");
+    vga_buffer::set_scrollback_size(vga_buffer::DEFAULT_SCROLLBACK_LINES);
+    #[cfg(feature = "framebuffer-console")]
+    blog_os::framebuffer::init(
+        blog_os::framebuffer::DEFAULT_WIDTH,
+        blog_os::framebuffer::DEFAULT_HEIGHT,
+    )
+    .expect("switching to graphics mode failed");
+    thread::init();
+    status_bar::enable();
+    println!("{}", memory::frame_stats());
 
     #[cfg(test)]
     test_main();
 
     println!("It did not crash!");
-    loop {}
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(example_task()));
+    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawn(Task::new(status_bar::refresh_periodically()));
+    executor.run();
+}
+
+async fn async_number() -> u32 {
+    42
+}
+
+async fn example_task() {
+    let number = async_number().await;
+    println!("async number: {}", number);
 }
 
 /// This function is called on panic.
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    loop {}
+    blog_os::vga_buffer::force_print(format_args!("{}\n", info));
+    blog_os::logger::dump_on_panic();
+    blog_os::hlt_loop();
 }
 
 #[cfg(test)]
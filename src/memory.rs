@@ -0,0 +1,238 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::fmt;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+/// Frames above this physical address are not managed by the frame allocator.
+pub const MAX_PHYSICAL_MEMORY: u64 = 4 * 1024 * 1024 * 1024;
+
+const FRAME_SIZE: u64 = 4096;
+const BITMAP_WORDS: usize = (MAX_PHYSICAL_MEMORY / FRAME_SIZE / 64) as usize;
+
+pub static FRAME_ALLOCATOR: spin::Mutex<BitmapFrameAllocator> =
+    spin::Mutex::new(BitmapFrameAllocator::new());
+
+/// A frame allocator that tracks every 4KiB frame of physical memory in a
+/// bitmap, so frames can be freed again in any order.
+pub struct BitmapFrameAllocator {
+    /// One bit per frame, set while the frame is usable and not allocated.
+    bitmap: [u64; BITMAP_WORDS],
+    /// One bit per frame, set if the memory map marks it as usable.
+    usable: [u64; BITMAP_WORDS],
+    total: usize,
+    used: usize,
+    /// Word to start the next search at; every word before it is full.
+    next_word: usize,
+}
+
+/// Why a frame could not be freed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeError {
+    /// The frame lies above [`MAX_PHYSICAL_MEMORY`].
+    NotManaged,
+    /// The frame is not usable memory, so it was never handed out.
+    NotUsable,
+    /// The frame is free already.
+    NotAllocated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frames: {} total, {} used, {} free ({} KiB free)",
+            self.total,
+            self.used,
+            self.free,
+            self.free as u64 * FRAME_SIZE / 1024
+        )
+    }
+}
+
+impl BitmapFrameAllocator {
+    /// Creates an allocator without any usable frames.
+    pub const fn new() -> Self {
+        BitmapFrameAllocator {
+            bitmap: [0; BITMAP_WORDS],
+            usable: [0; BITMAP_WORDS],
+            total: 0,
+            used: 0,
+            next_word: 0,
+        }
+    }
+
+    /// Marks every frame of the usable regions of `memory_map` as free.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed memory map is valid: all
+    /// frames marked as `USABLE` in it must really be unused.
+    pub unsafe fn init(&mut self, memory_map: &MemoryMap) {
+        let usable_regions = memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable);
+        for region in usable_regions {
+            let end = region.range.end_frame_number.min(BITMAP_WORDS as u64 * 64);
+            for frame in region.range.start_frame_number..end {
+                let (word, bit) = Self::position(frame as usize);
+                if self.usable[word] & bit == 0 {
+                    self.usable[word] |= bit;
+                    self.bitmap[word] |= bit;
+                    self.total += 1;
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            used: self.used,
+            free: self.total - self.used,
+        }
+    }
+
+    /// Returns an allocated frame, leaving the allocator unchanged if the
+    /// frame was never handed out or is free already.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the frame is no longer in use.
+    pub unsafe fn try_deallocate_frame(&mut self, frame: PhysFrame) -> Result<(), FreeError> {
+        let frame_number = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        let (word, bit) = Self::position(frame_number);
+        if word >= BITMAP_WORDS {
+            return Err(FreeError::NotManaged);
+        }
+        if self.usable[word] & bit == 0 {
+            return Err(FreeError::NotUsable);
+        }
+        if self.bitmap[word] & bit != 0 {
+            return Err(FreeError::NotAllocated);
+        }
+
+        self.bitmap[word] |= bit;
+        self.used -= 1;
+        self.next_word = self.next_word.min(word);
+        Ok(())
+    }
+
+    fn position(frame_number: usize) -> (usize, u64) {
+        (frame_number / 64, 1 << (frame_number % 64))
+    }
+}
+
+impl Default for BitmapFrameAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let word = (self.next_word..BITMAP_WORDS).find(|&word| self.bitmap[word] != 0)?;
+        self.next_word = word;
+
+        let bit = self.bitmap[word].trailing_zeros();
+        self.bitmap[word] &= !(1 << bit);
+        self.used += 1;
+
+        let frame_number = word as u64 * 64 + u64::from(bit);
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            frame_number * FRAME_SIZE,
+        )))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    /// Frees a frame, ignoring invalid frees in release builds so that the
+    /// stats stay consistent. See [`BitmapFrameAllocator::try_deallocate_frame`].
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let result = self.try_deallocate_frame(frame);
+        debug_assert!(result.is_ok(), "invalid free of {:?}: {:?}", frame, result);
+    }
+}
+
+/// Initializes the global [`FRAME_ALLOCATOR`] from the bootloader memory map.
+///
+/// # Safety
+///
+/// Same as [`BitmapFrameAllocator::init`]; must only be called once.
+pub unsafe fn init(memory_map: &'static MemoryMap) {
+    interrupts::without_interrupts(|| FRAME_ALLOCATOR.lock().init(memory_map));
+}
+
+pub fn allocate_frame() -> Option<PhysFrame> {
+    interrupts::without_interrupts(|| FRAME_ALLOCATOR.lock().allocate_frame())
+}
+
+/// Returns a frame to the global frame allocator.
+///
+/// # Safety
+///
+/// The caller must guarantee that the frame is no longer in use.
+pub unsafe fn deallocate_frame(frame: PhysFrame) {
+    interrupts::without_interrupts(|| FRAME_ALLOCATOR.lock().deallocate_frame(frame));
+}
+
+pub fn frame_stats() -> FrameStats {
+    interrupts::without_interrupts(|| FRAME_ALLOCATOR.lock().stats())
+}
+
+#[test_case]
+fn test_allocate_and_free_frames() {
+    let before = frame_stats();
+    assert!(before.total > 0);
+
+    let first = allocate_frame().expect("out of frames");
+    let second = allocate_frame().expect("out of frames");
+    assert_ne!(first, second);
+    assert_eq!(frame_stats().used, before.used + 2);
+
+    unsafe { deallocate_frame(first) };
+    assert_eq!(frame_stats().free, before.free - 1);
+    // the lowest free frame is handed out first, so the freed frame is reused
+    assert_eq!(allocate_frame(), Some(first));
+
+    unsafe {
+        deallocate_frame(first);
+        deallocate_frame(second);
+    }
+    assert_eq!(frame_stats(), before);
+}
+
+#[test_case]
+fn test_invalid_frees_are_rejected() {
+    let before = frame_stats();
+    let frame = allocate_frame().expect("out of frames");
+    interrupts::without_interrupts(|| {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        unsafe {
+            assert_eq!(allocator.try_deallocate_frame(frame), Ok(()));
+            assert_eq!(
+                allocator.try_deallocate_frame(frame),
+                Err(FreeError::NotAllocated)
+            );
+            // frame zero is never reported as usable
+            let zero = PhysFrame::containing_address(PhysAddr::new(0));
+            assert_eq!(
+                allocator.try_deallocate_frame(zero),
+                Err(FreeError::NotUsable)
+            );
+            let beyond = PhysFrame::containing_address(PhysAddr::new(MAX_PHYSICAL_MEMORY));
+            assert_eq!(
+                allocator.try_deallocate_frame(beyond),
+                Err(FreeError::NotManaged)
+            );
+        }
+    });
+    assert_eq!(frame_stats(), before);
+}
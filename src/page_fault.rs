@@ -0,0 +1,170 @@
+use core::fmt;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::VirtAddr;
+
+const MAX_REGIONS: usize = 16;
+
+/// Called for faults inside a registered region. Returns `true` if the fault
+/// was resolved (e.g. by mapping the page) and the access should be retried.
+pub type RecoveryHook = fn(&PageFault) -> bool;
+
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    start: VirtAddr,
+    end: VirtAddr,
+    hook: RecoveryHook,
+}
+
+static REGIONS: spin::Mutex<[Option<Region>; MAX_REGIONS]> = spin::Mutex::new([None; MAX_REGIONS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegistryFull;
+
+/// A decoded page fault: the faulting address from CR2 and the error code.
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    pub address: VirtAddr,
+    pub error_code: PageFaultErrorCode,
+}
+
+impl PageFault {
+    /// Reads CR2; must be called from the page fault handler before anything
+    /// else can fault.
+    pub fn read(error_code: PageFaultErrorCode) -> PageFault {
+        PageFault {
+            address: Cr2::read(),
+            error_code,
+        }
+    }
+
+    /// The page was present, so this was a protection violation.
+    pub fn is_present(&self) -> bool {
+        self.error_code
+            .contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.error_code
+            .contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+    }
+
+    pub fn is_user(&self) -> bool {
+        self.error_code.contains(PageFaultErrorCode::USER_MODE)
+    }
+
+    /// A reserved bit was set in one of the page table entries.
+    pub fn is_reserved_bit(&self) -> bool {
+        self.error_code
+            .contains(PageFaultErrorCode::MALFORMED_TABLE)
+    }
+
+    pub fn is_instruction_fetch(&self) -> bool {
+        self.error_code
+            .contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+    }
+}
+
+impl fmt::Display for PageFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = if self.is_instruction_fetch() {
+            "instruction fetch"
+        } else if self.is_write() {
+            "write"
+        } else {
+            "read"
+        };
+        writeln!(f, "Accessed Address: {:#x}", self.address.as_u64())?;
+        writeln!(f, "Error Code: {:#x}", self.error_code.bits())?;
+        writeln!(
+            f,
+            "  cause: {}",
+            if self.is_present() {
+                "protection violation"
+            } else {
+                "page not present"
+            }
+        )?;
+        writeln!(f, "  access: {}", access)?;
+        writeln!(
+            f,
+            "  mode: {}",
+            if self.is_user() { "user" } else { "kernel" }
+        )?;
+        write!(f, "  reserved bit set: {}", self.is_reserved_bit())
+    }
+}
+
+/// Registers `hook` for faults on addresses in `start..start + size`.
+pub fn register_region(start: VirtAddr, size: u64, hook: RecoveryHook) -> Result<(), RegistryFull> {
+    let region = Region {
+        start,
+        end: start + size,
+        hook,
+    };
+    interrupts::without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+        let slot = regions
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(RegistryFull)?;
+        *slot = Some(region);
+        Ok(())
+    })
+}
+
+/// Removes the region starting at `start`, if one was registered.
+pub fn unregister_region(start: VirtAddr) {
+    interrupts::without_interrupts(|| {
+        for slot in REGIONS.lock().iter_mut() {
+            if matches!(slot, Some(region) if region.start == start) {
+                *slot = None;
+            }
+        }
+    });
+}
+
+fn find_hook(address: VirtAddr) -> Option<RecoveryHook> {
+    REGIONS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|region| region.start <= address && address < region.end)
+        .map(|region| region.hook)
+}
+
+/// Gives the hook of the region containing the faulting address a chance to
+/// resolve the fault. The lock is released before the hook runs.
+pub fn try_recover(fault: &PageFault) -> bool {
+    match find_hook(fault.address) {
+        Some(hook) => hook(fault),
+        None => false,
+    }
+}
+
+#[test_case]
+fn test_region_lookup() {
+    fn hook(_fault: &PageFault) -> bool {
+        true
+    }
+    let start = VirtAddr::new(0x5555_0000_0000);
+    register_region(start, 0x1000, hook).unwrap();
+    assert!(find_hook(start + 0xfffu64).is_some());
+    assert!(find_hook(start + 0x1000u64).is_none());
+    unregister_region(start);
+    assert!(find_hook(start).is_none());
+}
+
+#[test_case]
+fn test_decode_error_code() {
+    let fault = PageFault {
+        address: VirtAddr::new(0xdeadbeef),
+        error_code: PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::USER_MODE,
+    };
+    assert!(!fault.is_present());
+    assert!(fault.is_write());
+    assert!(fault.is_user());
+    assert!(!fault.is_reserved_bit());
+    assert!(!fault.is_instruction_fetch());
+}
@@ -0,0 +1,300 @@
+use crate::memory::{self, FRAME_ALLOCATOR};
+use crate::serial_println;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{
+    FlagUpdateError, MapToError, TranslateResult, UnmapError,
+};
+use x86_64::structures::paging::{
+    Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+static MAPPER: spin::Mutex<Option<OffsetPageTable<'static>>> = spin::Mutex::new(None);
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub enum PagingError {
+    NotInitialized,
+    FrameAllocationFailed,
+    Map(MapToError<Size4KiB>),
+    Unmap(UnmapError),
+    FlagUpdate(FlagUpdateError),
+}
+
+impl From<MapToError<Size4KiB>> for PagingError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        PagingError::Map(err)
+    }
+}
+
+impl From<UnmapError> for PagingError {
+    fn from(err: UnmapError) -> Self {
+        PagingError::Unmap(err)
+    }
+}
+
+impl From<FlagUpdateError> for PagingError {
+    fn from(err: FlagUpdateError) -> Self {
+        PagingError::FlagUpdate(err)
+    }
+}
+
+/// Wraps the active level 4 table in the global `OffsetPageTable`.
+///
+/// # Safety
+///
+/// The caller must guarantee that the complete physical memory is mapped to
+/// virtual memory at the passed `physical_memory_offset`. Also, this function
+/// must be only called once to avoid aliasing `&mut` references.
+pub unsafe fn init(physical_memory_offset: VirtAddr) {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    interrupts::without_interrupts(|| {
+        *MAPPER.lock() = Some(OffsetPageTable::new(level_4_table, physical_memory_offset));
+    });
+}
+
+/// Returns a mutable reference to the active level 4 table.
+///
+/// # Safety
+///
+/// Same as [`init`].
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_table_frame, _) = Cr3::read();
+    &mut *phys_to_virt_with(physical_memory_offset, level_4_table_frame.start_address())
+        .as_mut_ptr()
+}
+
+fn phys_to_virt_with(physical_memory_offset: VirtAddr, addr: PhysAddr) -> VirtAddr {
+    physical_memory_offset + addr.as_u64()
+}
+
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// The address at which the given physical address is accessible through the
+/// physical memory mapping.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    phys_to_virt_with(physical_memory_offset(), addr)
+}
+
+fn with_mapper<T>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>) -> Result<T, PagingError>,
+) -> Result<T, PagingError> {
+    interrupts::without_interrupts(|| match MAPPER.lock().as_mut() {
+        Some(mapper) => f(mapper),
+        None => Err(PagingError::NotInitialized),
+    })
+}
+
+/// Maps `page` to `frame`, allocating intermediate page tables as needed.
+///
+/// # Safety
+///
+/// The caller must guarantee that the frame is not in use elsewhere and that
+/// the new mapping does not break memory safety (e.g. by aliasing).
+pub unsafe fn map_page(
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+) -> Result<(), PagingError> {
+    with_mapper(|mapper| {
+        mapper
+            .map_to(page, frame, flags, &mut *FRAME_ALLOCATOR.lock())?
+            .flush();
+        Ok(())
+    })
+}
+
+/// Maps `page` to a newly allocated frame and returns that frame.
+pub fn map_new_page(page: Page, flags: PageTableFlags) -> Result<PhysFrame, PagingError> {
+    let frame = memory::allocate_frame().ok_or(PagingError::FrameAllocationFailed)?;
+    // the frame is fresh and the page was unmapped, so nothing can alias it
+    match unsafe { map_page(page, frame, flags) } {
+        Ok(()) => Ok(frame),
+        Err(err) => {
+            unsafe { memory::deallocate_frame(frame) };
+            Err(err)
+        }
+    }
+}
+
+/// Removes the mapping of `page` and returns the frame it was mapped to.
+///
+/// # Safety
+///
+/// The caller must guarantee that nothing references memory in the page.
+pub unsafe fn unmap_page(page: Page) -> Result<PhysFrame, PagingError> {
+    with_mapper(|mapper| {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        Ok(frame)
+    })
+}
+
+/// Changes the flags of an existing mapping.
+///
+/// # Safety
+///
+/// The caller must guarantee that the new flags do not break memory safety,
+/// e.g. by making memory that is referenced immutably writable.
+pub unsafe fn update_flags(page: Page, flags: PageTableFlags) -> Result<(), PagingError> {
+    with_mapper(|mapper| {
+        mapper.update_flags(page, flags)?.flush();
+        Ok(())
+    })
+}
+
+/// Points the mapped `page` at `new_frame` and returns the frame it was
+/// mapped to before. On error the old mapping is left in place.
+///
+/// # Safety
+///
+/// The caller must guarantee the same as for [`unmap_page`] and [`map_page`].
+pub unsafe fn remap_page(
+    page: Page,
+    new_frame: PhysFrame,
+    flags: PageTableFlags,
+) -> Result<PhysFrame, PagingError> {
+    with_mapper(|mapper| {
+        let old_flags = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags,
+            _ => return Err(UnmapError::PageNotMapped.into()),
+        };
+        let (old_frame, flush) = mapper.unmap(page)?;
+        flush.ignore();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        match mapper.map_to(page, new_frame, flags, &mut *frame_allocator) {
+            Ok(flush) => {
+                flush.flush();
+                Ok(old_frame)
+            }
+            Err(err) => {
+                // the page tables down to level 1 still exist after the
+                // unmap, so restoring the old entry needs no allocation
+                mapper
+                    .map_to(page, old_frame, old_flags, &mut *frame_allocator)
+                    .expect("failed to restore the old mapping")
+                    .flush();
+                Err(err.into())
+            }
+        }
+    })
+}
+
+/// Translates a virtual address to the physical address it is mapped to.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    with_mapper(|mapper| Ok(mapper.translate_addr(addr)))
+        .ok()
+        .flatten()
+}
+
+/// The flags of the lowest-level entry mapping `addr`.
+pub fn flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    with_mapper(|mapper| match mapper.translate(addr) {
+        TranslateResult::Mapped { flags, .. } => Ok(Some(flags)),
+        _ => Ok(None),
+    })
+    .ok()
+    .flatten()
+}
+
+/// Prints the present entries of the active four-level table to serial,
+/// descending `levels` levels (1 prints only the level 4 table).
+pub fn dump_active_table(levels: usize) {
+    let (level_4_table_frame, _) = Cr3::read();
+    serial_println!(
+        "level 4 table at {:#x}",
+        level_4_table_frame.start_address().as_u64()
+    );
+    dump_table(level_4_table_frame.start_address(), 4, levels, 0);
+}
+
+fn dump_table(table_addr: PhysAddr, level: usize, levels: usize, base: u64) {
+    let table: &PageTable = unsafe { &*phys_to_virt(table_addr).as_ptr() };
+    let indent = (4 - level) * 2;
+    for (i, entry) in table.iter().enumerate() {
+        if entry.is_unused() {
+            continue;
+        }
+        let start = sign_extend(base | ((i as u64) << (12 + 9 * (level - 1))));
+        serial_println!(
+            "{:indent$}L{} [{:3}] {:#018x} -> {:#x} {:?}",
+            "",
+            level,
+            i,
+            start,
+            entry.addr().as_u64(),
+            entry.flags(),
+            indent = indent
+        );
+        let huge = entry.flags().contains(PageTableFlags::HUGE_PAGE);
+        if level > 1 && levels > 1 && !huge {
+            dump_table(entry.addr(), level - 1, levels - 1, start);
+        }
+    }
+}
+
+/// Makes a 48-bit virtual address canonical.
+fn sign_extend(addr: u64) -> u64 {
+    ((addr << 16) as i64 >> 16) as u64
+}
+
+#[test_case]
+fn test_translate_identity_mapped_vga_buffer() {
+    let vga = VirtAddr::new(0xb8000);
+    assert_eq!(translate(vga), Some(PhysAddr::new(0xb8000)));
+}
+
+#[test_case]
+fn test_map_remap_unmap() {
+    let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(0x_5000_0000_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let frame = map_new_page(page, flags).expect("map_new_page failed");
+    assert_eq!(translate(page.start_address()), Some(frame.start_address()));
+
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(0xf021_f077_f065_f04e);
+        let through_offset: *const u64 = phys_to_virt(frame.start_address()).as_ptr();
+        assert_eq!(through_offset.read_volatile(), 0xf021_f077_f065_f04e);
+
+        update_flags(page, PageTableFlags::PRESENT).unwrap();
+    }
+    let new_flags = self::flags(page.start_address()).unwrap();
+    assert!(!new_flags.contains(PageTableFlags::WRITABLE));
+
+    unsafe {
+        assert_eq!(unmap_page(page).unwrap(), frame);
+        memory::deallocate_frame(frame);
+    }
+    assert_eq!(translate(page.start_address()), None);
+}
+
+#[test_case]
+fn test_remap_to_new_frame() {
+    let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(0x_5000_0000_1000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let old_frame = map_new_page(page, flags).expect("map_new_page failed");
+    let new_frame = memory::allocate_frame().expect("out of frames");
+
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(1);
+        assert_eq!(remap_page(page, new_frame, flags).unwrap(), old_frame);
+        assert_eq!(
+            translate(page.start_address()),
+            Some(new_frame.start_address())
+        );
+        ptr.write_volatile(2);
+        let old: *const u64 = phys_to_virt(old_frame.start_address()).as_ptr();
+        assert_eq!(old.read_volatile(), 1);
+
+        assert_eq!(unmap_page(page).unwrap(), new_frame);
+        memory::deallocate_frame(old_frame);
+        memory::deallocate_frame(new_frame);
+    }
+}
@@ -0,0 +1,107 @@
+//! Reading the PCI configuration space through the legacy I/O ports.
+
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Bit of the address register that turns on configuration space access.
+const ENABLE: u32 = 1 << 31;
+
+/// Offset of the first base address register.
+const BAR0: u8 = 0x10;
+/// Bit of a BAR that marks it as an I/O port range instead of memory.
+const BAR_IO_SPACE: u32 = 1;
+const BAR_MEMORY_TYPE_64: u32 = 0b10 << 1;
+/// Bit of the header type, in the register at offset 0x0c.
+const MULTI_FUNCTION: u32 = 1 << 23;
+
+/// Vendor ID read when no function answers.
+const NO_DEVICE: u16 = 0xffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// Reads the aligned 32-bit register at `offset`.
+    pub fn read(self, offset: u8) -> u32 {
+        let address = ENABLE
+            | u32::from(self.bus) << 16
+            | u32::from(self.device) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset & 0xfc);
+        // the two port accesses must not be split by a handler doing the same
+        interrupts::without_interrupts(|| unsafe {
+            Port::new(CONFIG_ADDRESS).write(address);
+            Port::<u32>::new(CONFIG_DATA).read()
+        })
+    }
+
+    pub fn vendor_id(self) -> u16 {
+        self.read(0x00) as u16
+    }
+
+    pub fn device_id(self) -> u16 {
+        (self.read(0x00) >> 16) as u16
+    }
+
+    /// Whether the device has functions other than 0, read from function 0.
+    fn is_multi_function(self) -> bool {
+        self.read(0x0c) & MULTI_FUNCTION != 0
+    }
+
+    /// The physical address of the memory region of base address register
+    /// `index`, `None` if it is an I/O port range.
+    pub fn memory_bar(self, index: u8) -> Option<u64> {
+        let offset = BAR0 + 4 * index;
+        let low = self.read(offset);
+        if low & BAR_IO_SPACE != 0 {
+            return None;
+        }
+        let mut address = u64::from(low & !0xf);
+        if low & BAR_MEMORY_TYPE_64 != 0 {
+            address |= u64::from(self.read(offset + 4)) << 32;
+        }
+        Some(address)
+    }
+}
+
+/// The first function with the given vendor and device ID, searching all
+/// buses.
+pub fn find_device(vendor_id: u16, device_id: u16) -> Option<PciAddress> {
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let mut address = PciAddress {
+                bus,
+                device,
+                function: 0,
+            };
+            if address.vendor_id() == NO_DEVICE {
+                continue;
+            }
+            let functions = if address.is_multi_function() { 8 } else { 1 };
+            for function in 0..functions {
+                address.function = function;
+                if address.vendor_id() == vendor_id && address.device_id() == device_id {
+                    return Some(address);
+                }
+            }
+        }
+    }
+    None
+}
+
+#[test_case]
+fn test_host_bridge_present() {
+    let host_bridge = PciAddress {
+        bus: 0,
+        device: 0,
+        function: 0,
+    };
+    assert_ne!(host_bridge.vendor_id(), NO_DEVICE);
+}
@@ -0,0 +1,167 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use pic8259::ChainedPics;
+use spin;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+/// The primary PIC is remapped to the first vector after the 32 CPU exceptions.
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xA0;
+const CMD_END_OF_INTERRUPT: u8 = 0x20;
+const CMD_READ_ISR: u8 = 0x0B;
+
+/// The line on the primary PIC that the secondary PIC is chained through.
+const CASCADE_IRQ: u8 = 2;
+
+pub static PICS: spin::Mutex<ChainedPics> =
+    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// Handled interrupts of each IRQ line, counted at their end of interrupt.
+static INTERRUPT_COUNTS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard,
+    Cascade,
+    Com2,
+    Com1,
+    Lpt2,
+    Floppy,
+    Lpt1,
+    Rtc = PIC_2_OFFSET,
+    Free1,
+    Free2,
+    Free3,
+    Mouse,
+    Fpu,
+    PrimaryAta,
+    SecondaryAta,
+}
+
+impl InterruptIndex {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn as_usize(self) -> usize {
+        usize::from(self.as_u8())
+    }
+
+    /// The IRQ line (0-15) this vector is raised for.
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+}
+
+/// Remaps both PICs above the CPU exceptions and masks every line except the
+/// cascade. Drivers unmask the lines they handle with [`unmask`].
+pub fn init() {
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.initialize();
+        pics.write_masks(!(1 << CASCADE_IRQ), u8::MAX);
+    }
+}
+
+/// Signals the end of an interrupt to the PIC (or both PICs for lines on the
+/// secondary one).
+pub fn end_of_interrupt(index: InterruptIndex) {
+    INTERRUPT_COUNTS[usize::from(index.irq())].fetch_add(1, Ordering::Relaxed);
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(index.as_u8());
+    }
+}
+
+/// Number of interrupts handled on a line since boot. Spurious interrupts are
+/// not counted.
+pub fn interrupt_count(index: InterruptIndex) -> u64 {
+    INTERRUPT_COUNTS[usize::from(index.irq())].load(Ordering::Relaxed)
+}
+
+pub fn mask(index: InterruptIndex) {
+    update_masks(|masks| {
+        let (pic, bit) = line(index.irq());
+        masks[pic] |= 1 << bit;
+    });
+}
+
+/// Unmasks the line, also unmasking the cascade for lines on the secondary PIC.
+pub fn unmask(index: InterruptIndex) {
+    update_masks(|masks| {
+        let (pic, bit) = line(index.irq());
+        masks[pic] &= !(1 << bit);
+        if pic == 1 {
+            masks[0] &= !(1 << CASCADE_IRQ);
+        }
+    });
+}
+
+pub fn is_masked(index: InterruptIndex) -> bool {
+    let (pic, bit) = line(index.irq());
+    let masks = interrupts::without_interrupts(|| unsafe { PICS.lock().read_masks() });
+    masks[pic] & (1 << bit) != 0
+}
+
+/// Checks whether an interrupt on IRQ 7 or IRQ 15 is spurious, i.e. the
+/// corresponding bit is not set in the in-service register. A spurious IRQ 15
+/// still needs an end of interrupt on the primary PIC, which is sent here.
+pub fn is_spurious(index: InterruptIndex) -> bool {
+    let (pic, bit) = line(index.irq());
+    let command = if pic == 0 {
+        PIC_1_COMMAND
+    } else {
+        PIC_2_COMMAND
+    };
+    let _pics = PICS.lock();
+    unsafe {
+        let mut port: Port<u8> = Port::new(command);
+        port.write(CMD_READ_ISR);
+        if port.read() & (1 << bit) != 0 {
+            return false;
+        }
+        if pic == 1 {
+            Port::<u8>::new(PIC_1_COMMAND).write(CMD_END_OF_INTERRUPT);
+        }
+    }
+    true
+}
+
+/// Splits an IRQ line into the PIC it belongs to and its bit in that PIC.
+fn line(irq: u8) -> (usize, u8) {
+    if irq < 8 {
+        (0, irq)
+    } else {
+        (1, irq - 8)
+    }
+}
+
+fn update_masks<F: FnOnce(&mut [u8; 2])>(f: F) {
+    interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        unsafe {
+            let mut masks = pics.read_masks();
+            f(&mut masks);
+            pics.write_masks(masks[0], masks[1]);
+        }
+    });
+}
+
+#[test_case]
+fn test_mask_unmask() {
+    // keep interrupts off so the unmasked line cannot fire without a handler
+    interrupts::without_interrupts(|| {
+        let index = InterruptIndex::Rtc;
+        mask(index);
+        assert!(is_masked(index));
+        unmask(index);
+        assert!(!is_masked(index));
+        assert!(!is_masked(InterruptIndex::Cascade));
+        mask(index);
+        assert!(is_masked(index));
+    });
+}
@@ -1,24 +1,53 @@
+use crate::sync::IrqSpinLock;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use uart_16550::SerialPort;
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
+    pub static ref SERIAL1: IrqSpinLock<SerialPort> = {
         let mut serial_port = unsafe { SerialPort::new(0x3F8) };
         serial_port.init();
-        Mutex::new(serial_port)
+        IrqSpinLock::new(serial_port)
     };
 }
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
+    // interrupts stay disabled while the port is locked, so a handler that
+    // prints cannot deadlock on it
     SERIAL1
         .lock()
         .write_fmt(args)
         .expect("Printing to serial failed");
 }
 
+/// Prints if the port is not locked, without waiting for it otherwise.
+/// Returns whether the output was printed.
+pub fn try_print(args: ::core::fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    match SERIAL1.try_lock() {
+        Some(mut serial) => {
+            serial.write_fmt(args).expect("Printing to serial failed");
+            true
+        }
+        None => false,
+    }
+}
+
+/// Prints even if the port is locked, for panic and exception context where
+/// the holder may never unlock it again.
+pub fn force_print(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+    let mut serial = match SERIAL1.try_lock() {
+        Some(serial) => serial,
+        None => {
+            unsafe { SERIAL1.force_unlock() };
+            SERIAL1.lock()
+        }
+    };
+    serial.write_fmt(args).expect("Printing to serial failed");
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
@@ -0,0 +1,142 @@
+//! A line at the bottom of the screen showing the state of the kernel.
+
+use crate::pic::{self, InterruptIndex};
+use crate::task::executor;
+use crate::vga_buffer::CONSOLES;
+use crate::{allocator, framebuffer, memory, thread, timer};
+use core::fmt;
+use core::time::Duration;
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelStatus {
+    pub uptime: Duration,
+    /// Free physical memory in bytes.
+    pub free_memory: usize,
+    /// Free heap memory in bytes, before the heap would have to grow.
+    pub free_heap: usize,
+    /// Threads that are running or ready, not counting the idle thread.
+    pub runnable_threads: usize,
+    /// Async tasks queued for polling in any executor.
+    pub ready_tasks: usize,
+    pub timer_interrupts: u64,
+    pub keyboard_interrupts: u64,
+}
+
+impl KernelStatus {
+    pub fn collect() -> KernelStatus {
+        let (_, free_heap) = allocator::heap_usage();
+        KernelStatus {
+            uptime: timer::uptime(),
+            free_memory: memory::frame_stats().free * Size4KiB::SIZE as usize,
+            free_heap,
+            runnable_threads: thread::runnable_count(),
+            ready_tasks: executor::ready_tasks(),
+            timer_interrupts: pic::interrupt_count(InterruptIndex::Timer),
+            keyboard_interrupts: pic::interrupt_count(InterruptIndex::Keyboard),
+        }
+    }
+}
+
+/// Renders as ` up H:MM:SS mem <free>K heap <free>K run <threads>/<tasks>
+/// irq <timer>/<keyboard>`, which stays within the 80 columns of the status
+/// bar up to 9999 hours of uptime and 10 digit interrupt counts.
+impl fmt::Display for KernelStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.uptime.as_secs();
+        write!(
+            f,
+            " up {}:{:02}:{:02} mem {}K heap {}K run {}/{} irq {}/{}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            self.free_memory / 1024,
+            self.free_heap / 1024,
+            self.runnable_threads,
+            self.ready_tasks,
+            self.timer_interrupts,
+            self.keyboard_interrupts
+        )
+    }
+}
+
+/// Reserves the bottom row of the screen for the status bar and draws it.
+/// Requires the frame allocator, the heap and threads to be initialized.
+///
+/// The status bar is drawn on the VGA text consoles only, so this does
+/// nothing in graphics mode.
+pub fn enable() {
+    if framebuffer::is_enabled() {
+        return;
+    }
+    CONSOLES.lock().set_status_bar(true);
+    refresh();
+}
+
+/// Gives the bottom row back to the consoles.
+pub fn disable() {
+    CONSOLES.lock().set_status_bar(false);
+}
+
+/// Redraws the status bar with the current state.
+pub fn refresh() {
+    if framebuffer::is_enabled() {
+        return;
+    }
+    // collected first, so that no other lock is taken with the consoles locked
+    let status = KernelStatus::collect();
+    CONSOLES.lock().draw_status_bar(format_args!("{}", status));
+}
+
+/// Task that refreshes the status bar every [`REFRESH_INTERVAL`].
+pub async fn refresh_periodically() {
+    loop {
+        refresh();
+        timer::delay(REFRESH_INTERVAL).await;
+    }
+}
+
+#[test_case]
+fn test_status_bar_reserves_bottom_row() {
+    use crate::vga_buffer::ConsoleId;
+
+    enable();
+    let mut consoles = CONSOLES.lock();
+    assert!(consoles.has_status_bar());
+    let writer = consoles.get_mut(ConsoleId::MAIN);
+    // text cannot reach the status bar
+    writer.write_string("\x1b[30;1Hbottom\n");
+    assert_eq!(writer.cursor_position().0, 23);
+    drop(consoles);
+
+    refresh();
+    let bottom_row = unsafe { (0xb8000 as *const u16).add(24 * 80) };
+    let text: heapless::String<80> = (0..5)
+        .map(|col| char::from(unsafe { bottom_row.add(col).read_volatile() } as u8))
+        .collect();
+    assert_eq!(text, " up 0");
+    disable();
+}
+
+#[test_case]
+fn test_status_bar_fits_large_values() {
+    let status = KernelStatus {
+        uptime: Duration::from_secs(9999 * 3600 + 59 * 60 + 59),
+        free_memory: 99_999_999 * 1024,
+        free_heap: 9_999_999 * 1024,
+        runnable_threads: 999,
+        ready_tasks: 999,
+        timer_interrupts: 9_999_999_999,
+        keyboard_interrupts: 1_234_567_890,
+    };
+    enable();
+    CONSOLES.lock().draw_status_bar(format_args!("{}", status));
+    let bottom_row = unsafe { (0xb8000 as *const u16).add(24 * 80) };
+    let text: heapless::String<80> = (0..80)
+        .map(|col| char::from(unsafe { bottom_row.add(col).read_volatile() } as u8))
+        .collect();
+    assert!(text.ends_with(" irq 9999999999/1234567890"));
+    disable();
+}
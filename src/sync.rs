@@ -0,0 +1,20 @@
+//! Locks and wait primitives for kernel threads and async tasks.
+//!
+//! The blocking primitives park the current thread on a [`WaitQueue`] instead
+//! of spinning. Before [`crate::thread::init`] they fall back to spinning.
+//! [`IrqSpinLock`] never blocks and is the lock to share data with interrupt
+//! handlers.
+
+mod condvar;
+mod irq_spin_lock;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod wait_queue;
+
+pub use condvar::Condvar;
+pub use irq_spin_lock::{IrqSpinLock, IrqSpinLockGuard};
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use wait_queue::WaitQueue;
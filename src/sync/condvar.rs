@@ -0,0 +1,77 @@
+use super::{Mutex, MutexGuard, WaitQueue};
+
+/// A condition variable to wait for a change of the data behind a [`Mutex`].
+///
+/// Wakeups can be spurious, so the condition has to be checked in a loop or
+/// with [`Condvar::wait_while`].
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex and parks the current thread until notified, then
+    /// locks the mutex again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex: &'a Mutex<T> = MutexGuard::mutex(&guard);
+        // unlocking after queueing means a notification right after the
+        // unlock cannot be missed
+        self.waiters.park_with(|| drop(guard));
+        mutex.lock()
+    }
+
+    /// Waits until `condition` returns false for the data behind the mutex.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) -> bool {
+        self.waiters.wake_one()
+    }
+
+    pub fn notify_all(&self) -> usize {
+        self.waiters.wake_all()
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test_case]
+fn test_condvar_hands_over_values() {
+    use crate::thread;
+
+    static VALUE: Mutex<Option<u32>> = Mutex::new(None);
+    static CHANGED: Condvar = Condvar::new();
+
+    let consumer = thread::spawn(|| {
+        for expected in 1..=5 {
+            let mut value = CHANGED.wait_while(VALUE.lock(), |value| value.is_none());
+            assert_eq!(value.take(), Some(expected));
+            CHANGED.notify_all();
+        }
+    })
+    .unwrap();
+    for next in 1..=5 {
+        let mut value = CHANGED.wait_while(VALUE.lock(), |value| value.is_some());
+        *value = Some(next);
+        CHANGED.notify_all();
+    }
+    thread::join(consumer);
+    assert_eq!(*VALUE.lock(), None);
+}
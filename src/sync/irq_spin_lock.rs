@@ -0,0 +1,104 @@
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use x86_64::instructions::interrupts;
+
+/// A spin lock that disables interrupts while it is held, so an interrupt
+/// handler on the same CPU can never find it locked by the code it
+/// interrupted.
+pub struct IrqSpinLock<T: ?Sized> {
+    inner: spin::Mutex<T>,
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        IrqSpinLock {
+            inner: spin::Mutex::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqSpinLockGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            were_enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqSpinLockGuard {
+                guard: ManuallyDrop::new(guard),
+                were_enabled,
+            }),
+            None => {
+                if were_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    /// Unlocks the lock without a guard.
+    ///
+    /// # Safety
+    ///
+    /// Only for recovering from a situation where the holder can never unlock
+    /// it again, such as a panic. Any guard still alive must not be used.
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+}
+
+/// Re-enables interrupts when dropped if they were enabled before locking.
+pub struct IrqSpinLockGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    were_enabled: bool,
+}
+
+impl<T: ?Sized> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // unlocked before interrupts are enabled again
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.were_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+#[test_case]
+fn test_irq_spin_lock_disables_interrupts() {
+    let lock = IrqSpinLock::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(!interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*lock.lock(), 1);
+}
@@ -0,0 +1,164 @@
+use super::{IrqSpinLock, WaitQueue};
+use crate::thread::{self, ThreadId};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A mutual exclusion lock that parks waiting threads instead of spinning.
+///
+/// A thread waiting for the lock lends its priority to the holder until the
+/// lock is released. Priority lent through other mutexes the holder still
+/// holds is kept.
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    owner: IrqSpinLock<Option<ThreadId>>,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            locked: AtomicBool::new(false),
+            owner: IrqSpinLock::new(None),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            let current = thread::try_current();
+            self.waiters.wait_until(|| {
+                let locked = self.locked.load(Ordering::Acquire);
+                if let (true, Some(owner), Some(current)) = (locked, *self.owner.lock(), current) {
+                    thread::inherit_priority(owner, current, self.resource());
+                }
+                !locked
+            });
+        }
+    }
+
+    /// Locks the mutex from an async task, waiting without blocking the thread.
+    pub async fn lock_async(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            self.waiters
+                .until(|| !self.locked.load(Ordering::Acquire))
+                .await;
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        *self.owner.lock() = thread::try_current();
+        Some(MutexGuard {
+            mutex: self,
+            _not_send_sync: PhantomData,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Identifies the mutex for priority inheritance.
+    fn resource(&self) -> usize {
+        self as *const Self as *const u8 as usize
+    }
+
+    fn unlock(&self) {
+        if let Some(owner) = self.owner.lock().take() {
+            thread::clear_inherited_priority(owner, self.resource());
+        }
+        self.locked.store(false, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    /// The guard hands out `&T`, so it may only be shared if `T: Sync`.
+    _not_send_sync: PhantomData<*const ()>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    pub(super) fn mutex(guard: &Self) -> &'a Mutex<T> {
+        guard.mutex
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+#[test_case]
+fn test_mutex_excludes_threads() {
+    use alloc::vec::Vec;
+
+    static COUNTER: Mutex<usize> = Mutex::new(0);
+
+    let ids: Vec<ThreadId> = (0..4)
+        .map(|_| {
+            thread::spawn(|| {
+                for _ in 0..200 {
+                    let mut counter = COUNTER.lock();
+                    let value = *counter;
+                    // give other threads a chance to run while the lock is held
+                    thread::yield_now();
+                    *counter = value + 1;
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    for id in ids {
+        thread::join(id);
+    }
+    assert_eq!(*COUNTER.lock(), 800);
+}
@@ -0,0 +1,161 @@
+use super::WaitQueue;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Set in the state while a writer holds the lock; the other bits count the
+/// readers.
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+/// A reader-writer lock that parks waiting threads.
+///
+/// Writers are not preferred, so a steady stream of readers can starve them.
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        RwLock {
+            state: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.waiters.wait_until(|| self.try_lock_read());
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.waiters.wait_until(|| self.try_lock_write());
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.try_lock_read()
+            .then_some(RwLockReadGuard { lock: self })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.try_lock_write()
+            .then_some(RwLockWriteGuard { lock: self })
+    }
+
+    fn try_lock_read(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state & WRITER == 0 {
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => state = current,
+            }
+        }
+        false
+    }
+
+    fn try_lock_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) & !WRITER
+    }
+
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            // the last reader lets a writer in
+            self.lock.waiters.wake_all();
+        }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        // all waiting readers can proceed at once
+        self.lock.waiters.wake_all();
+    }
+}
+
+#[test_case]
+fn test_rwlock_readers_share_writers_exclude() {
+    use crate::thread;
+
+    static LOCK: RwLock<u64> = RwLock::new(0);
+
+    let first = LOCK.read();
+    let second = LOCK.try_read().expect("readers must share the lock");
+    assert_eq!(LOCK.reader_count(), 2);
+    assert!(LOCK.try_write().is_none());
+
+    let writer = thread::spawn(|| *LOCK.write() += 1).unwrap();
+    thread::yield_now();
+    // the writer waits until both readers are gone
+    assert_eq!(*first, 0);
+    drop(first);
+    drop(second);
+    thread::join(writer);
+    assert_eq!(*LOCK.read(), 1);
+    assert!(!LOCK.is_write_locked());
+}
@@ -0,0 +1,103 @@
+use super::WaitQueue;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A counting semaphore.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Takes a permit, blocking the current thread until one is available.
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| self.try_acquire());
+    }
+
+    /// Takes a permit from an async task.
+    pub async fn acquire_async(&self) {
+        self.waiters.until(|| self.try_acquire()).await
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+        false
+    }
+
+    /// Returns a permit and wakes a waiter. Can be called from interrupt
+    /// handlers.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+#[test_case]
+fn test_semaphore_limits_concurrency() {
+    use crate::thread::{self, ThreadId};
+    use alloc::vec::Vec;
+
+    static SEMAPHORE: Semaphore = Semaphore::new(2);
+    static INSIDE: AtomicUsize = AtomicUsize::new(0);
+    static MAX_INSIDE: AtomicUsize = AtomicUsize::new(0);
+
+    let ids: Vec<ThreadId> = (0..5)
+        .map(|_| {
+            thread::spawn(|| {
+                SEMAPHORE.acquire();
+                let inside = INSIDE.fetch_add(1, Ordering::SeqCst) + 1;
+                MAX_INSIDE.fetch_max(inside, Ordering::SeqCst);
+                thread::yield_now();
+                INSIDE.fetch_sub(1, Ordering::SeqCst);
+                SEMAPHORE.release();
+            })
+            .unwrap()
+        })
+        .collect();
+    for id in ids {
+        thread::join(id);
+    }
+    assert_eq!(MAX_INSIDE.load(Ordering::SeqCst), 2);
+    assert_eq!(SEMAPHORE.available_permits(), 2);
+}
+
+#[test_case]
+fn test_semaphore_wakes_task() {
+    use crate::task::{executor::Executor, Task};
+
+    static SEMAPHORE: Semaphore = Semaphore::new(0);
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        SEMAPHORE.acquire_async().await;
+        DONE.store(1, Ordering::SeqCst);
+    }));
+    executor.spawn(Task::new(async {
+        crate::task::yield_now().await;
+        SEMAPHORE.release();
+    }));
+    executor.run_until_complete();
+    assert_eq!(DONE.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,270 @@
+use super::IrqSpinLock;
+use crate::thread::{self, ThreadId};
+use alloc::collections::VecDeque;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use x86_64::instructions::interrupts;
+
+enum Waiter {
+    Thread(ThreadId),
+    /// A task waiting in the [`WaitUntil`] registered under the key.
+    Task(u64, Waker),
+}
+
+impl Waiter {
+    fn wake(self) {
+        match self {
+            Waiter::Thread(id) => thread::unpark(id),
+            Waiter::Task(_, waker) => waker.wake(),
+        }
+    }
+}
+
+/// A queue of threads and tasks waiting for something, woken in FIFO order.
+///
+/// Wakeups can be spurious, so waiters have to check their condition again;
+/// [`WaitQueue::wait_until`] does this.
+pub struct WaitQueue {
+    waiters: IrqSpinLock<VecDeque<Waiter>>,
+    next_key: AtomicU64,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: IrqSpinLock::new(VecDeque::new()),
+            next_key: AtomicU64::new(0),
+        }
+    }
+
+    /// Parks the current thread on the queue until it is woken. `before_park`
+    /// runs after the thread was queued, with interrupts disabled, so nothing
+    /// it does (e.g. unlocking a mutex) can wake another thread that then
+    /// notifies this queue before the thread is parked.
+    ///
+    /// Before threads are initialized this only runs `before_park` and
+    /// returns, i.e. it is a spurious wakeup.
+    pub fn park_with(&self, before_park: impl FnOnce()) {
+        interrupts::without_interrupts(|| match thread::try_current() {
+            Some(current) => {
+                self.waiters.lock().push_back(Waiter::Thread(current));
+                before_park();
+                thread::park();
+            }
+            None => {
+                before_park();
+                core::hint::spin_loop();
+            }
+        })
+    }
+
+    /// Blocks the current thread until `condition` returns true. The condition
+    /// is checked with interrupts disabled.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            let done = interrupts::without_interrupts(|| {
+                if condition() {
+                    return true;
+                }
+                self.park_with(|| {});
+                false
+            });
+            if done {
+                return;
+            }
+        }
+    }
+
+    /// A future that completes once `condition` returns true, for tasks.
+    pub fn until<F: FnMut() -> bool>(&self, condition: F) -> WaitUntil<'_, F> {
+        WaitUntil {
+            queue: self,
+            condition,
+            key: None,
+        }
+    }
+
+    /// Registers a task to be woken with the next notification. If the
+    /// registration under `key` is still queued, only its waker is updated, so
+    /// polling the same future again does not take a second place in the queue.
+    fn register(&self, key: &mut Option<u64>, waker: &Waker) {
+        let mut waiters = self.waiters.lock();
+        if let Some(key) = *key {
+            let queued = waiters.iter_mut().find_map(|waiter| match waiter {
+                Waiter::Task(k, queued) if *k == key => Some(queued),
+                _ => None,
+            });
+            if let Some(queued) = queued {
+                if !queued.will_wake(waker) {
+                    *queued = waker.clone();
+                }
+                return;
+            }
+        }
+        let new_key = self.next_key.fetch_add(1, Ordering::Relaxed);
+        waiters.push_back(Waiter::Task(new_key, waker.clone()));
+        *key = Some(new_key);
+    }
+
+    /// Removes the registration under `key` if it was not woken yet.
+    fn unregister(&self, key: u64) {
+        self.waiters
+            .lock()
+            .retain(|waiter| !matches!(waiter, Waiter::Task(k, _) if *k == key));
+    }
+
+    /// Wakes the longest waiting thread or task. Returns whether there was one.
+    pub fn wake_one(&self) -> bool {
+        let waiter = self.waiters.lock().pop_front();
+        match waiter {
+            Some(waiter) => {
+                waiter.wake();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wakes every waiting thread and task and returns how many there were.
+    pub fn wake_all(&self) -> usize {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        let count = waiters.len();
+        for waiter in waiters {
+            waiter.wake();
+        }
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiters.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct WaitUntil<'a, F> {
+    queue: &'a WaitQueue,
+    condition: F,
+    /// Key of this future's place in the queue, if it registered one.
+    key: Option<u64>,
+}
+
+impl<F: FnMut() -> bool + Unpin> Future for WaitUntil<'_, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        interrupts::without_interrupts(|| {
+            if (this.condition)() {
+                if let Some(key) = this.key.take() {
+                    this.queue.unregister(key);
+                }
+                return Poll::Ready(());
+            }
+            this.queue.register(&mut this.key, cx.waker());
+            Poll::Pending
+        })
+    }
+}
+
+impl<F> Drop for WaitUntil<'_, F> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.queue.unregister(key);
+        }
+    }
+}
+
+#[test_case]
+fn test_wait_queue_wakes_threads_in_order() {
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static QUEUE: WaitQueue = WaitQueue::new();
+    static WOKEN: AtomicUsize = AtomicUsize::new(0);
+    static ORDER: IrqSpinLock<Vec<usize>> = IrqSpinLock::new(Vec::new());
+
+    let ids: Vec<ThreadId> = (0..3)
+        .map(|i| {
+            thread::spawn(move || {
+                QUEUE.park_with(|| {});
+                ORDER.lock().push(i);
+                WOKEN.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap()
+        })
+        .collect();
+    while QUEUE.len() < 3 {
+        thread::yield_now();
+    }
+    assert!(QUEUE.wake_one());
+    while WOKEN.load(Ordering::SeqCst) < 1 {
+        thread::yield_now();
+    }
+    assert_eq!(QUEUE.wake_all(), 2);
+    for id in ids {
+        thread::join(id);
+    }
+    assert_eq!(*ORDER.lock(), [0, 1, 2]);
+}
+
+#[test_case]
+fn test_repolled_task_waits_once() {
+    use alloc::sync::Arc;
+    use alloc::task::Wake;
+    use core::sync::atomic::{AtomicBool, AtomicUsize};
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let queue = WaitQueue::new();
+    let released = AtomicBool::new(false);
+    let first = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let second = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let first_waker = Waker::from(first.clone());
+    let second_waker = Waker::from(second.clone());
+    let mut first_cx = Context::from_waker(&first_waker);
+    let mut second_cx = Context::from_waker(&second_waker);
+
+    let mut first_wait = queue.until(|| released.load(Ordering::SeqCst));
+    let mut second_wait = queue.until(|| released.load(Ordering::SeqCst));
+    assert_eq!(Pin::new(&mut first_wait).poll(&mut first_cx), Poll::Pending);
+    assert_eq!(Pin::new(&mut first_wait).poll(&mut first_cx), Poll::Pending);
+    assert_eq!(
+        Pin::new(&mut second_wait).poll(&mut second_cx),
+        Poll::Pending
+    );
+    assert_eq!(queue.len(), 2);
+
+    released.store(true, Ordering::SeqCst);
+    assert!(queue.wake_one());
+    assert_eq!(first.0.load(Ordering::SeqCst), 1);
+    assert!(queue.wake_one());
+    assert_eq!(second.0.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        Pin::new(&mut first_wait).poll(&mut first_cx),
+        Poll::Ready(())
+    );
+
+    // a dropped waiter gives up its place
+    let mut dropped = queue.until(|| false);
+    assert_eq!(Pin::new(&mut dropped).poll(&mut first_cx), Poll::Pending);
+    assert_eq!(queue.len(), 1);
+    drop(dropped);
+    assert!(queue.is_empty());
+}
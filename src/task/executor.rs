@@ -0,0 +1,268 @@
+use super::{Task, TaskId};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+/// Maximum number of tasks that can be queued for polling at once.
+const TASK_QUEUE_SIZE: usize = 128;
+
+/// Number of tasks queued for polling, in all executors.
+static READY_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of tasks that are ready to be polled, in all executors.
+pub fn ready_tasks() -> usize {
+    READY_TASKS.load(Ordering::Relaxed)
+}
+
+/// The queue of an executor's ready tasks, counted in [`READY_TASKS`].
+struct TaskQueue(ArrayQueue<TaskId>);
+
+impl TaskQueue {
+    fn new() -> Self {
+        TaskQueue(ArrayQueue::new(TASK_QUEUE_SIZE))
+    }
+
+    fn push(&self, task_id: TaskId) -> Result<(), TaskId> {
+        // counted first, so that a concurrent pop never takes the count below 0
+        READY_TASKS.fetch_add(1, Ordering::Relaxed);
+        let result = self.0.push(task_id);
+        if result.is_err() {
+            READY_TASKS.fetch_sub(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn pop(&self) -> Option<TaskId> {
+        let task_id = self.0.pop()?;
+        READY_TASKS.fetch_sub(1, Ordering::Relaxed);
+        Some(task_id)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Drop for TaskQueue {
+    // wakers keep the queue alive, so nothing can push to it anymore
+    fn drop(&mut self) {
+        READY_TASKS.fetch_sub(self.0.len(), Ordering::Relaxed);
+    }
+}
+
+/// Polls tasks only after they were woken and halts the CPU while no task is
+/// ready.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<TaskQueue>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(TaskQueue::new()),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    /// Runs the tasks forever.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Runs until all tasks have completed.
+    pub fn run_until_complete(&mut self) {
+        loop {
+            self.run_ready_tasks();
+            if self.tasks.is_empty() {
+                break;
+            }
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        // destructure `self` to avoid borrow checker errors
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+        } = self;
+
+        while let Some(task_id) = task_queue.pop() {
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue, // task no longer exists
+            };
+            let task_waker = waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            // wakeups from now on must queue the task again
+            task_waker.queued.store(false, Ordering::Release);
+            let waker = Waker::from(task_waker.clone());
+            let mut context = Context::from_waker(&waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    // task done -> remove it and its cached waker
+                    tasks.remove(&task_id);
+                    waker_cache.remove(&task_id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    /// Halts until the next interrupt if no task is ready. Interrupts are
+    /// disabled for the check, so a wakeup from an interrupt handler cannot
+    /// slip in between the check and the `hlt`.
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        if self.task_queue.is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<TaskQueue>,
+    /// Set while the task is in the queue, so repeated wakeups (e.g. from
+    /// interrupt handlers) queue it only once.
+    queued: AtomicBool,
+}
+
+impl TaskWaker {
+    fn new(task_id: TaskId, task_queue: Arc<TaskQueue>) -> Arc<Self> {
+        Arc::new(TaskWaker {
+            task_id,
+            task_queue,
+            queued: AtomicBool::new(false),
+        })
+    }
+
+    /// May run in interrupt context, so it must not panic. The queue holds
+    /// every task at most once, so it can only be full if more than
+    /// [`TASK_QUEUE_SIZE`] tasks exist; the wakeup is dropped then.
+    fn wake_task(&self) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if self.task_queue.push(self.task_id).is_err() {
+            self.queued.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+#[test_case]
+fn test_many_tasks() {
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    for _ in 0..100 {
+        executor.spawn(Task::new(async {
+            crate::task::yield_now().await;
+            COMPLETED.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    executor.run_until_complete();
+    assert_eq!(COMPLETED.load(Ordering::SeqCst), 100);
+}
+
+#[test_case]
+fn test_chained_awaits() {
+    static RESULT: AtomicUsize = AtomicUsize::new(0);
+
+    async fn add(a: usize, b: usize) -> usize {
+        crate::task::yield_now().await;
+        a + b
+    }
+
+    async fn sum(n: usize) -> usize {
+        let mut total = 0;
+        for i in 1..=n {
+            total = add(total, i).await;
+        }
+        total
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        RESULT.store(sum(10).await, Ordering::SeqCst);
+    }));
+    executor.run_until_complete();
+    assert_eq!(RESULT.load(Ordering::SeqCst), 55);
+}
+
+#[test_case]
+fn test_repeated_wakeups_queue_task_once() {
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(core::future::poll_fn(|cx| {
+        if POLLS.fetch_add(1, Ordering::SeqCst) > 0 {
+            return Poll::Ready(());
+        }
+        for _ in 0..2 * TASK_QUEUE_SIZE {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    })));
+    executor.run_until_complete();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 2);
+    assert_eq!(ready_tasks(), 0);
+}
+
+#[test_case]
+fn test_wakeup_from_timer_interrupt() {
+    use crate::timer::{self, Instant};
+    use core::time::Duration;
+
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+    let start = Instant::now();
+    let mut executor = Executor::new();
+    for i in 1..=4 {
+        executor.spawn(Task::new(async move {
+            timer::delay(Duration::from_millis(5 * i)).await;
+            COMPLETED.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    executor.run_until_complete();
+    assert_eq!(COMPLETED.load(Ordering::SeqCst), 4);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
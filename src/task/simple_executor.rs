@@ -0,0 +1,69 @@
+use super::Task;
+use alloc::collections::VecDeque;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// Polls its tasks round-robin, whether or not they were woken.
+pub struct SimpleExecutor {
+    task_queue: VecDeque<Task>,
+}
+
+impl SimpleExecutor {
+    pub fn new() -> SimpleExecutor {
+        SimpleExecutor {
+            task_queue: VecDeque::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        self.task_queue.push_back(task)
+    }
+
+    /// Runs until all tasks have completed.
+    pub fn run(&mut self) {
+        while let Some(mut task) = self.task_queue.pop_front() {
+            let waker = dummy_waker();
+            let mut context = Context::from_waker(&waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {} // task done
+                Poll::Pending => self.task_queue.push_back(task),
+            }
+        }
+    }
+}
+
+impl Default for SimpleExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn dummy_raw_waker() -> RawWaker {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        dummy_raw_waker()
+    }
+
+    let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
+    RawWaker::new(core::ptr::null::<()>(), vtable)
+}
+
+fn dummy_waker() -> Waker {
+    unsafe { Waker::from_raw(dummy_raw_waker()) }
+}
+
+#[test_case]
+fn test_simple_executor_runs_all_tasks() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = SimpleExecutor::new();
+    for _ in 0..10 {
+        executor.spawn(Task::new(async {
+            super::yield_now().await;
+            COMPLETED.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    executor.run();
+    assert_eq!(COMPLETED.load(Ordering::SeqCst), 10);
+}
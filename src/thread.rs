@@ -0,0 +1,589 @@
+use crate::paging::PagingError;
+use crate::serial_println;
+use crate::timer::Instant;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+pub mod scheduler;
+pub mod stack;
+mod switch;
+
+pub(crate) use scheduler::preempt;
+pub use scheduler::Priority;
+use scheduler::{reschedule, Scheduler, SCHEDULER};
+use stack::Stack;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Ready,
+    /// Waiting in [`join`].
+    Blocked,
+    /// Finished but not yet joined or reaped.
+    Exited,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // `pad` so that width and alignment flags apply
+        f.pad(match self {
+            State::Running => "running",
+            State::Ready => "ready",
+            State::Blocked => "blocked",
+            State::Exited => "exited",
+        })
+    }
+}
+
+/// Scheduling accounting of a thread.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadStats {
+    /// Time spent running.
+    pub runtime: Duration,
+    /// Number of times the thread was switched to.
+    pub switches: u64,
+    /// Time spent ready but waiting for the CPU.
+    pub wait: Duration,
+    running_since: Instant,
+    ready_since: Instant,
+}
+
+impl ThreadStats {
+    fn new() -> Self {
+        let now = Instant::now();
+        ThreadStats {
+            runtime: Duration::ZERO,
+            switches: 0,
+            wait: Duration::ZERO,
+            running_since: now,
+            ready_since: now,
+        }
+    }
+}
+
+type ThreadMain = Box<dyn FnOnce() + Send + 'static>;
+
+/// Maximum number of resources a thread tracks inherited levels for.
+const MAX_INHERITED: usize = 8;
+
+pub struct Thread {
+    id: ThreadId,
+    state: State,
+    priority: Priority,
+    /// Feedback queue the thread is in, at or below the level of its priority.
+    level: usize,
+    /// Levels inherited from higher priority threads waiting for resources
+    /// this thread holds, as `(resource, level)` pairs.
+    inherited: heapless::Vec<(usize, usize), MAX_INHERITED>,
+    stats: ThreadStats,
+    /// Stack pointer saved when the thread was switched away from.
+    rsp: u64,
+    /// `None` for the boot thread, which runs on the bootloader stack.
+    stack: Option<Stack>,
+    /// Threads blocked in [`join`] on this thread.
+    joiners: Vec<ThreadId>,
+}
+
+impl Thread {
+    fn boot() -> Thread {
+        Thread {
+            id: ThreadId::new(),
+            state: State::Running,
+            priority: Priority::Normal,
+            level: Priority::Normal.level(),
+            inherited: heapless::Vec::new(),
+            stats: ThreadStats::new(),
+            rsp: 0,
+            stack: None,
+            joiners: Vec::new(),
+        }
+    }
+
+    fn new(main: ThreadMain, priority: Priority) -> Result<Thread, PagingError> {
+        let stack = Stack::allocate()?;
+        let arg = Box::into_raw(Box::new(main));
+        let rsp = unsafe { switch::initial_stack(stack.top().as_u64(), arg as u64) };
+        Ok(Thread {
+            id: ThreadId::new(),
+            state: State::Ready,
+            priority,
+            level: priority.level(),
+            inherited: heapless::Vec::new(),
+            stats: ThreadStats::new(),
+            rsp,
+            stack: Some(stack),
+            joiners: Vec::new(),
+        })
+    }
+
+    /// The level the thread is scheduled at.
+    fn effective_level(&self) -> usize {
+        self.inherited
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.level, usize::min)
+    }
+}
+
+/// First Rust code run by a new thread, entered from the trampoline.
+extern "C" fn thread_start(main: *mut ThreadMain) -> ! {
+    let main = unsafe { Box::from_raw(main) };
+    interrupts::enable();
+    main();
+    exit();
+}
+
+/// Turns the running code into the boot thread and starts the idle thread.
+/// Requires the heap, as thread state lives there.
+pub fn init() {
+    let boot = Thread::boot();
+    let idle = Thread::new(Box::new(idle), Priority::Batch).expect("creating idle thread failed");
+    let idle_id = idle.id;
+    interrupts::without_interrupts(|| {
+        let mut scheduler = Scheduler::new(boot);
+        scheduler.threads.insert(idle_id, Box::new(idle));
+        scheduler.idle = idle_id;
+        scheduler.reserve_queues();
+        *SCHEDULER.lock() = Some(scheduler);
+    });
+}
+
+fn idle() {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Starts a new kernel thread running `main` with [`Priority::Normal`]. Also
+/// frees the stacks of exited threads nobody joined yet.
+pub fn spawn<F>(main: F) -> Result<ThreadId, PagingError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_priority(main, Priority::Normal)
+}
+
+pub fn spawn_with_priority<F>(main: F, priority: Priority) -> Result<ThreadId, PagingError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    reap();
+    let thread = Thread::new(Box::new(main), priority)?;
+    let id = thread.id;
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .as_mut()
+            .expect("threads not initialized")
+            .add(thread)
+    });
+    Ok(id)
+}
+
+/// The thread the caller runs on.
+pub fn current() -> ThreadId {
+    try_current().expect("threads not initialized")
+}
+
+/// The thread the caller runs on, or `None` before [`init`].
+pub fn try_current() -> Option<ThreadId> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().as_ref().map(|scheduler| scheduler.current))
+}
+
+/// Blocks the current thread until [`unpark`] is called for it.
+///
+/// Must be called with interrupts disabled, which lets the caller register the
+/// thread somewhere before blocking without racing against the `unpark`.
+pub(crate) fn park() {
+    reschedule(SCHEDULER.lock(), State::Blocked);
+}
+
+/// Makes a parked thread ready to run again. Does nothing if the thread is not
+/// blocked. Can be called from interrupt handlers.
+pub fn unpark(id: ThreadId) {
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        if let Some(scheduler) = guard.as_mut() {
+            if scheduler.threads.get(&id).map(|thread| thread.state) == Some(State::Blocked) {
+                scheduler.make_ready(id);
+            }
+        }
+    })
+}
+
+/// Lets the other ready threads run before the current one continues.
+pub fn yield_now() {
+    interrupts::without_interrupts(|| reschedule(SCHEDULER.lock(), State::Ready));
+}
+
+/// Ends the current thread, waking the threads that wait to join it.
+pub fn exit() -> ! {
+    interrupts::disable();
+    let mut guard = SCHEDULER.lock();
+    let scheduler = guard.as_mut().expect("threads not initialized");
+    let current = scheduler.current;
+    assert!(
+        scheduler.threads[&current].stack.is_some(),
+        "the boot thread cannot exit"
+    );
+    let joiners = core::mem::take(&mut scheduler.thread_mut(current).joiners);
+    for joiner in joiners {
+        if scheduler.threads[&joiner].state == State::Blocked {
+            scheduler.make_ready(joiner);
+        }
+    }
+    reschedule(guard, State::Exited);
+    unreachable!("exited thread was scheduled again");
+}
+
+/// Blocks until the thread `id` has exited and frees it. Returns right away
+/// if there is no such thread (anymore).
+pub fn join(id: ThreadId) {
+    loop {
+        let finished = interrupts::without_interrupts(|| {
+            let mut guard = SCHEDULER.lock();
+            let scheduler = guard.as_mut().expect("threads not initialized");
+            assert_ne!(id, scheduler.current, "a thread cannot join itself");
+            match scheduler.threads.get_mut(&id) {
+                None => Some(None),
+                Some(thread) if thread.state == State::Exited => {
+                    Some(scheduler.threads.remove(&id))
+                }
+                Some(thread) => {
+                    let current = scheduler.current;
+                    thread.joiners.push(current);
+                    reschedule(guard, State::Blocked);
+                    None
+                }
+            }
+        });
+        if let Some(thread) = finished {
+            // dropped with interrupts enabled, as unmapping its stack takes a while
+            drop(thread);
+            return;
+        }
+    }
+}
+
+fn with_scheduler<T>(f: impl FnOnce(&mut Scheduler) -> T) -> T {
+    interrupts::without_interrupts(|| {
+        f(SCHEDULER.lock().as_mut().expect("threads not initialized"))
+    })
+}
+
+/// Changes the base priority of a thread and moves it back to its level.
+/// Returns false if there is no such thread.
+pub fn set_priority(id: ThreadId, priority: Priority) -> bool {
+    with_scheduler(|scheduler| {
+        if !scheduler.threads.contains_key(&id) {
+            return false;
+        }
+        scheduler.set_level(id, |thread| {
+            thread.priority = priority;
+            thread.level = priority.level();
+        });
+        true
+    })
+}
+
+/// Priority inheritance hook for blocking primitives: lets `holder` run at
+/// least at the level of `waiter`, which waits for `resource`, until
+/// [`clear_inherited_priority`] is called for that resource. This way a lower
+/// priority thread holding a resource cannot delay a higher priority thread
+/// waiting for it indefinitely.
+///
+/// `resource` is any value identifying the resource, e.g. its address.
+pub fn inherit_priority(holder: ThreadId, waiter: ThreadId, resource: usize) {
+    with_scheduler(|scheduler| {
+        let level = match scheduler.threads.get(&waiter) {
+            Some(waiter) => waiter.effective_level(),
+            None => return,
+        };
+        if scheduler.threads.contains_key(&holder) {
+            scheduler.set_level(holder, |thread| {
+                let inherited = &mut thread.inherited;
+                match inherited.iter_mut().find(|(r, _)| *r == resource) {
+                    Some((_, inherited)) => *inherited = (*inherited).min(level),
+                    None => {
+                        if let Err((_, level)) = inherited.push((resource, level)) {
+                            // out of slots, so the last resource keeps the
+                            // level until it is released
+                            let last = inherited.last_mut().unwrap();
+                            last.1 = last.1.min(level);
+                        }
+                    }
+                }
+            });
+        }
+    })
+}
+
+/// Drops the priority `holder` inherited through `resource`, e.g. when it
+/// releases it. Levels inherited through resources it still holds stay.
+pub fn clear_inherited_priority(holder: ThreadId, resource: usize) {
+    with_scheduler(|scheduler| {
+        if scheduler.threads.contains_key(&holder) {
+            scheduler.set_level(holder, |thread| {
+                thread.inherited.retain(|&(r, _)| r != resource)
+            });
+        }
+    })
+}
+
+pub fn state(id: ThreadId) -> Option<State> {
+    with_scheduler(|scheduler| scheduler.threads.get(&id).map(|thread| thread.state))
+}
+
+/// Number of threads that are running or ready to run, not counting the idle
+/// thread.
+pub fn runnable_count() -> usize {
+    with_scheduler(|scheduler| {
+        scheduler
+            .threads
+            .values()
+            .filter(|thread| thread.id != scheduler.idle)
+            .filter(|thread| matches!(thread.state, State::Running | State::Ready))
+            .count()
+    })
+}
+
+/// The scheduling statistics of a thread. For the running thread, the time of
+/// the current run is not included yet.
+pub fn stats(id: ThreadId) -> Option<ThreadStats> {
+    with_scheduler(|scheduler| scheduler.threads.get(&id).map(|thread| thread.stats))
+}
+
+/// Prints the scheduling state and statistics of every thread to serial.
+pub fn dump_stats() {
+    let now = Instant::now();
+    // collected first, so that printing happens without the scheduler lock
+    let threads: Vec<_> = with_scheduler(|scheduler| {
+        let current = scheduler.current;
+        scheduler
+            .threads
+            .values()
+            .map(|thread| {
+                let mut stats = thread.stats;
+                if thread.id == current {
+                    stats.runtime += now.duration_since(stats.running_since);
+                }
+                (
+                    thread.id,
+                    thread.state,
+                    thread.priority,
+                    thread.effective_level(),
+                    stats,
+                )
+            })
+            .collect()
+    });
+    serial_println!(
+        "{:>4} {:<8} {:<8} {:>5} {:>12} {:>9} {:>12}",
+        "id",
+        "state",
+        "priority",
+        "level",
+        "runtime(ms)",
+        "switches",
+        "wait(ms)"
+    );
+    for (id, state, priority, level, stats) in threads {
+        serial_println!(
+            "{:>4} {:<8} {:<8} {:>5} {:>12} {:>9} {:>12}",
+            id.as_u64(),
+            state,
+            priority,
+            level,
+            stats.runtime.as_millis(),
+            stats.switches,
+            stats.wait.as_millis()
+        );
+    }
+}
+
+/// Removes all exited threads that nobody joined.
+fn reap() {
+    let exited: Vec<Box<Thread>> = interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = match guard.as_mut() {
+            Some(scheduler) => scheduler,
+            None => return Vec::new(),
+        };
+        let ids: Vec<ThreadId> = scheduler
+            .threads
+            .values()
+            .filter(|thread| thread.state == State::Exited)
+            .map(|thread| thread.id)
+            .collect();
+        ids.iter()
+            .filter_map(|id| scheduler.threads.remove(id))
+            .collect()
+    });
+    drop(exited);
+}
+
+#[test_case]
+fn test_spawn_and_join() {
+    use core::sync::atomic::AtomicBool;
+
+    static RAN: AtomicBool = AtomicBool::new(false);
+    let id = spawn(|| RAN.store(true, Ordering::SeqCst)).unwrap();
+    join(id);
+    assert!(RAN.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn test_exit_skips_rest_of_thread() {
+    use core::sync::atomic::AtomicUsize;
+
+    static STEPS: AtomicUsize = AtomicUsize::new(0);
+    let id = spawn(|| {
+        STEPS.fetch_add(1, Ordering::SeqCst);
+        exit();
+    })
+    .unwrap();
+    join(id);
+    assert_eq!(STEPS.load(Ordering::SeqCst), 1);
+}
+
+#[test_case]
+fn test_yield_alternates_threads() {
+    use core::sync::atomic::AtomicUsize;
+
+    static TURN: AtomicUsize = AtomicUsize::new(0);
+    let mut ids = Vec::new();
+    for parity in 0..2 {
+        ids.push(
+            spawn(move || {
+                for _ in 0..10 {
+                    while TURN.load(Ordering::SeqCst) % 2 != parity {
+                        yield_now();
+                    }
+                    TURN.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap(),
+        );
+    }
+    for id in ids {
+        join(id);
+    }
+    assert_eq!(TURN.load(Ordering::SeqCst), 20);
+}
+
+#[test_case]
+fn test_higher_priority_runs_first() {
+    static ORDER: spin::Mutex<Vec<Priority>> = spin::Mutex::new(Vec::new());
+    let ids: Vec<ThreadId> = [Priority::Batch, Priority::Low, Priority::High]
+        .iter()
+        .map(|&priority| {
+            spawn_with_priority(move || ORDER.lock().push(priority), priority).unwrap()
+        })
+        .collect();
+    for id in ids {
+        join(id);
+    }
+    assert_eq!(
+        *ORDER.lock(),
+        [Priority::High, Priority::Low, Priority::Batch]
+    );
+}
+
+#[test_case]
+fn test_inherited_priority() {
+    // undo any demotion of the boot thread and start a new time slice
+    set_priority(current(), Priority::Normal);
+    yield_now();
+
+    let id = spawn_with_priority(|| {}, Priority::Batch).unwrap();
+    yield_now();
+    assert_eq!(stats(id).unwrap().switches, 0);
+    inherit_priority(id, current(), 0);
+    yield_now();
+    assert_eq!(stats(id).unwrap().switches, 1);
+    assert_eq!(state(id), Some(State::Exited));
+    join(id);
+}
+
+#[test_case]
+fn test_inherited_priority_is_kept_per_resource() {
+    set_priority(current(), Priority::Normal);
+    yield_now();
+
+    let level = |id| with_scheduler(|scheduler| scheduler.threads[&id].effective_level());
+    let id = spawn_with_priority(|| {}, Priority::Batch).unwrap();
+    inherit_priority(id, current(), 1);
+    inherit_priority(id, current(), 2);
+    let boosted = level(id);
+    assert!(boosted < Priority::Batch.level());
+    clear_inherited_priority(id, 2);
+    // still waited for on resource 1
+    assert_eq!(level(id), boosted);
+    clear_inherited_priority(id, 1);
+    assert_eq!(level(id), Priority::Batch.level());
+    join(id);
+}
+
+#[test_case]
+fn test_stats_are_accounted() {
+    use crate::timer;
+
+    let id = spawn(|| timer::spin_sleep(Duration::from_millis(30))).unwrap();
+    while state(id) != Some(State::Exited) {
+        yield_now();
+    }
+    let stats = stats(id).unwrap();
+    assert!(stats.switches >= 1);
+    assert!(stats.runtime > Duration::ZERO, "{:?}", stats);
+    join(id);
+    dump_stats();
+}
+
+#[test_case]
+fn test_cpu_bound_threads_are_preempted() {
+    use crate::timer;
+    use core::sync::atomic::{AtomicBool, AtomicUsize};
+
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static COUNTERS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+    let ids: Vec<ThreadId> = (0..2)
+        .map(|i| {
+            spawn(move || {
+                // never yields, so only preemption lets the other run
+                while !STOP.load(Ordering::SeqCst) {
+                    COUNTERS[i].fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    timer::sleep(Duration::from_millis(100));
+    let progress = [
+        COUNTERS[0].load(Ordering::SeqCst),
+        COUNTERS[1].load(Ordering::SeqCst),
+    ];
+    STOP.store(true, Ordering::SeqCst);
+    for id in ids {
+        join(id);
+    }
+    assert!(progress[0] > 0 && progress[1] > 0, "{:?}", progress);
+}
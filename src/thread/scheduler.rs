@@ -0,0 +1,254 @@
+use super::switch::switch_context;
+use super::{State, Thread, ThreadId};
+use crate::timer::{self, Instant};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::MutexGuard;
+
+/// Number of feedback queues. Level 0 runs first.
+pub const LEVELS: usize = 4;
+
+/// Default time slice of each level in timer ticks. Lower levels get longer
+/// slices, since the threads there are CPU-bound and switching them often
+/// only costs throughput.
+pub const DEFAULT_TIME_SLICES: [u64; LEVELS] = [5, 10, 20, 40];
+
+/// Ticks after which every thread is moved back to the level of its base
+/// priority, so that demoted threads cannot starve.
+pub const BOOST_INTERVAL_TICKS: u64 = 1000;
+
+static TIME_SLICES: [AtomicU64; LEVELS] = [
+    AtomicU64::new(DEFAULT_TIME_SLICES[0]),
+    AtomicU64::new(DEFAULT_TIME_SLICES[1]),
+    AtomicU64::new(DEFAULT_TIME_SLICES[2]),
+    AtomicU64::new(DEFAULT_TIME_SLICES[3]),
+];
+
+pub(super) static SCHEDULER: spin::Mutex<Option<Scheduler>> = spin::Mutex::new(None);
+
+/// The base priority of a thread. A thread starts at the level of its
+/// priority and is demoted one level whenever it uses up a whole time slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,
+    Normal,
+    Low,
+    Batch,
+}
+
+impl Priority {
+    pub fn level(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+            Priority::Batch => "batch",
+        })
+    }
+}
+
+/// Sets the time slice of a level in timer ticks (at least 1).
+pub fn set_time_slice(level: usize, ticks: u64) {
+    TIME_SLICES[level].store(ticks.max(1), Ordering::Relaxed);
+}
+
+pub fn time_slice(level: usize) -> u64 {
+    TIME_SLICES[level].load(Ordering::Relaxed)
+}
+
+pub(super) struct Scheduler {
+    /// Boxed so that the saved stack pointers keep their address while the
+    /// map changes.
+    pub threads: BTreeMap<ThreadId, Box<Thread>>,
+    /// Ready threads of each level in the order they will run. The capacity
+    /// of each queue is kept at least at the number of threads, so pushing
+    /// never allocates.
+    pub run_queues: [VecDeque<ThreadId>; LEVELS],
+    pub current: ThreadId,
+    /// Runs only when no other thread is ready.
+    pub idle: ThreadId,
+    /// Tick at which the current thread was switched to.
+    pub slice_start: u64,
+    /// Tick of the last priority boost.
+    pub last_boost: u64,
+}
+
+impl Scheduler {
+    pub fn new(boot: Thread) -> Scheduler {
+        let current = boot.id;
+        let mut threads = BTreeMap::new();
+        threads.insert(current, Box::new(boot));
+        Scheduler {
+            threads,
+            run_queues: Default::default(),
+            current,
+            idle: current,
+            slice_start: timer::ticks(),
+            last_boost: timer::ticks(),
+        }
+    }
+
+    pub fn add(&mut self, thread: Thread) {
+        let id = thread.id;
+        self.threads.insert(id, Box::new(thread));
+        self.reserve_queues();
+        self.make_ready(id);
+    }
+
+    pub fn reserve_queues(&mut self) {
+        let count = self.threads.len();
+        for queue in &mut self.run_queues {
+            queue.reserve(count.saturating_sub(queue.len()));
+        }
+    }
+
+    pub fn thread_mut(&mut self, id: ThreadId) -> &mut Thread {
+        self.threads.get_mut(&id).expect("unknown thread")
+    }
+
+    pub fn make_ready(&mut self, id: ThreadId) {
+        let idle = self.idle;
+        let thread = self.thread_mut(id);
+        thread.state = State::Ready;
+        thread.stats.ready_since = Instant::now();
+        let level = thread.effective_level();
+        if id != idle {
+            self.run_queues[level].push_back(id);
+        }
+    }
+
+    fn next_ready(&mut self) -> Option<ThreadId> {
+        self.run_queues
+            .iter_mut()
+            .find_map(|queue| queue.pop_front())
+    }
+
+    /// The highest level with a ready thread.
+    fn highest_ready_level(&self) -> Option<usize> {
+        self.run_queues.iter().position(|queue| !queue.is_empty())
+    }
+
+    /// Changes the level of a thread, moving it between run queues if it is
+    /// ready.
+    pub fn set_level(&mut self, id: ThreadId, update: impl FnOnce(&mut Thread)) {
+        let idle = self.idle;
+        let thread = self.thread_mut(id);
+        let old_level = thread.effective_level();
+        update(thread);
+        let new_level = thread.effective_level();
+        if thread.state == State::Ready && id != idle && old_level != new_level {
+            let queue = &mut self.run_queues[old_level];
+            if let Some(index) = queue.iter().position(|&queued| queued == id) {
+                queue.remove(index);
+                self.run_queues[new_level].push_back(id);
+            }
+        }
+    }
+
+    /// Moves every thread back to the level of its base priority.
+    fn boost(&mut self) {
+        for thread in self.threads.values_mut() {
+            thread.level = thread.priority.level();
+        }
+        // boosting only lowers level numbers, so every thread is moved to a
+        // queue that was already visited or stays in its own
+        for level in 0..LEVELS {
+            for _ in 0..self.run_queues[level].len() {
+                let id = self.run_queues[level].pop_front().unwrap();
+                let new_level = self.threads[&id].effective_level();
+                self.run_queues[new_level].push_back(id);
+            }
+        }
+        self.last_boost = timer::ticks();
+    }
+}
+
+/// Puts the current thread into `state` and switches to the next ready
+/// thread. A thread that stays `Ready` is queued before the next thread is
+/// picked, so it keeps running unless a thread of the same or a higher level
+/// is ready. The idle thread runs if nothing else can.
+///
+/// Must be called with interrupts disabled. Returns once the current thread
+/// is switched back to.
+pub(super) fn reschedule(mut guard: MutexGuard<Option<Scheduler>>, state: State) {
+    let scheduler = guard.as_mut().expect("threads not initialized");
+    let current = scheduler.current;
+    let now = Instant::now();
+    if state == State::Ready {
+        scheduler.make_ready(current);
+    } else {
+        scheduler.thread_mut(current).state = state;
+    }
+    let next = scheduler.next_ready().unwrap_or(scheduler.idle);
+    scheduler.slice_start = timer::ticks();
+    if next == current {
+        scheduler.thread_mut(current).state = State::Running;
+        return;
+    }
+
+    let thread = scheduler.thread_mut(current);
+    thread.stats.runtime += now.duration_since(thread.stats.running_since);
+    let thread = scheduler.thread_mut(next);
+    thread.state = State::Running;
+    thread.stats.switches += 1;
+    thread.stats.wait += now.duration_since(thread.stats.ready_since);
+    thread.stats.running_since = now;
+    scheduler.current = next;
+
+    let current_rsp: *mut u64 = &mut scheduler.thread_mut(current).rsp;
+    let next_rsp = scheduler.thread_mut(next).rsp;
+    drop(guard);
+    // the current thread stays in the map at least until it is switched away
+    // from, since only other threads remove exited threads
+    unsafe { switch_context(current_rsp, next_rsp) };
+}
+
+/// Called by the timer interrupt handler after the end of interrupt was sent.
+///
+/// Switches away from the current thread when it has used up the time slice
+/// of its level, which also demotes it, or when a thread of a higher level is
+/// ready. The idle thread is preempted as soon as any thread is ready.
+pub(crate) fn preempt() {
+    // the lock is only held with interrupts disabled, so this fails only if
+    // the interrupted code is the scheduler itself
+    let mut guard = match SCHEDULER.try_lock() {
+        Some(guard) => guard,
+        None => return,
+    };
+    let scheduler = match guard.as_mut() {
+        Some(scheduler) => scheduler,
+        None => return,
+    };
+    let now = timer::ticks();
+    if now - scheduler.last_boost >= BOOST_INTERVAL_TICKS {
+        scheduler.boost();
+    }
+
+    let highest_ready = match scheduler.highest_ready_level() {
+        Some(level) => level,
+        None => return,
+    };
+    let current = scheduler.current;
+    if current == scheduler.idle {
+        reschedule(guard, State::Ready);
+        return;
+    }
+    let level = scheduler.thread_mut(current).effective_level();
+    let slice_used = now - scheduler.slice_start >= time_slice(level);
+    if slice_used {
+        scheduler.set_level(current, |thread| {
+            thread.level = (thread.level + 1).min(LEVELS - 1)
+        });
+    }
+    if slice_used || highest_ready < level {
+        reschedule(guard, State::Ready);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::memory;
+use crate::paging::{self, PagingError};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+/// Start of the virtual region the thread stacks are placed in.
+const STACKS_START: u64 = 0x_5555_0000_0000;
+/// Mapped pages of each stack.
+pub const STACK_PAGES: u64 = 4;
+const PAGE_SIZE: u64 = 4096;
+/// Every slot starts with an unmapped guard page below the stack, so an
+/// overflow page faults instead of silently corrupting the slot below.
+const SLOT_SIZE: u64 = (STACK_PAGES + 1) * PAGE_SIZE;
+
+static NEXT_SLOT: AtomicU64 = AtomicU64::new(0);
+/// Slots whose stacks were freed and can be mapped again.
+static FREE_SLOTS: spin::Mutex<Vec<u64>> = spin::Mutex::new(Vec::new());
+
+/// A mapped kernel stack with a guard page below it. The pages are unmapped
+/// and their frames freed when the stack is dropped.
+#[derive(Debug)]
+pub struct Stack {
+    slot: u64,
+}
+
+impl Stack {
+    pub fn allocate() -> Result<Stack, PagingError> {
+        let slot = interrupts::without_interrupts(|| FREE_SLOTS.lock().pop())
+            .unwrap_or_else(|| NEXT_SLOT.fetch_add(1, Ordering::Relaxed));
+        let stack = Stack { slot };
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        for (mapped, page) in stack.pages().enumerate() {
+            if let Err(err) = paging::map_new_page(page, flags) {
+                stack.unmap(mapped);
+                interrupts::without_interrupts(|| FREE_SLOTS.lock().push(slot));
+                core::mem::forget(stack);
+                return Err(err);
+            }
+        }
+        Ok(stack)
+    }
+
+    pub fn guard_page(&self) -> Page<Size4KiB> {
+        Page::containing_address(VirtAddr::new(STACKS_START + self.slot * SLOT_SIZE))
+    }
+
+    pub fn bottom(&self) -> VirtAddr {
+        self.guard_page().start_address() + PAGE_SIZE
+    }
+
+    /// The address just past the highest byte of the stack.
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + STACK_PAGES * PAGE_SIZE
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page<Size4KiB>> {
+        let first = self.guard_page() + 1;
+        Page::range(first, first + STACK_PAGES)
+    }
+
+    /// Unmaps the first `count` pages and frees their frames.
+    fn unmap(&self, count: usize) {
+        for page in self.pages().take(count) {
+            // nothing runs on the stack anymore
+            let frame = unsafe { paging::unmap_page(page) }.expect("unmapping stack failed");
+            unsafe { memory::deallocate_frame(frame) };
+        }
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        self.unmap(STACK_PAGES as usize);
+        let slot = self.slot;
+        interrupts::without_interrupts(|| FREE_SLOTS.lock().push(slot));
+    }
+}
+
+/// Whether `addr` lies in the guard page of a thread stack.
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    let addr = addr.as_u64();
+    let end = STACKS_START + NEXT_SLOT.load(Ordering::Relaxed) * SLOT_SIZE;
+    (STACKS_START..end).contains(&addr) && (addr - STACKS_START) % SLOT_SIZE < PAGE_SIZE
+}
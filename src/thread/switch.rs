@@ -0,0 +1,74 @@
+use core::arch::global_asm;
+
+// Saves the callee-saved registers and the flags of the current thread on its
+// stack, stores its stack pointer to `*old_rsp` and restores the registers of
+// the thread whose stack pointer is `new_rsp`. The caller-saved registers are
+// already saved by the compiler around the call.
+global_asm!(
+    r#"
+.global switch_context
+switch_context:
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    pushfq
+    mov [rdi], rsp
+    mov rsp, rsi
+    popfq
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    ret
+
+.global thread_trampoline
+thread_trampoline:
+    mov rdi, r12
+    and rsp, -16
+    call {thread_start}
+    ud2
+"#,
+    thread_start = sym super::thread_start,
+);
+
+extern "C" {
+    /// # Safety
+    ///
+    /// `new_rsp` must be the stack pointer saved by a previous switch away from
+    /// a thread or prepared by [`initial_stack`]. Must be called with
+    /// interrupts disabled.
+    pub fn switch_context(old_rsp: *mut u64, new_rsp: u64);
+    fn thread_trampoline();
+}
+
+/// Initial RFLAGS of a thread: only the reserved bit is set, interrupts are
+/// enabled once the thread has started.
+const INITIAL_RFLAGS: u64 = 0x2;
+
+/// Prepares the stack below `stack_top` so that the first switch to it enters
+/// `thread_start` with `arg` as argument. Returns the initial stack pointer.
+///
+/// # Safety
+///
+/// `stack_top` must be the 16-byte aligned top of an unused, mapped stack.
+pub unsafe fn initial_stack(stack_top: u64, arg: u64) -> u64 {
+    let frame: [u64; 9] = [
+        INITIAL_RFLAGS,
+        0,   // r15
+        0,   // r14
+        0,   // r13
+        arg, // r12
+        0,   // rbx
+        0,   // rbp
+        thread_trampoline as unsafe extern "C" fn() as usize as u64,
+        0, // never used, keeps the frame 16-byte aligned
+    ];
+    let rsp = stack_top - (frame.len() * 8) as u64;
+    (rsp as *mut [u64; 9]).write(frame);
+    rsp
+}
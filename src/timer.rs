@@ -0,0 +1,230 @@
+use crate::pic::{self, InterruptIndex};
+use crate::sync::IrqSpinLock;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+/// Input clock of the 8253/8254 PIT in Hz.
+pub const PIT_BASE_FREQUENCY: u32 = 1_193_182;
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+// channel 0, lobyte/hibyte access, mode 3 (square wave), binary
+const PIT_MODE_SQUARE_WAVE: u8 = 0b0011_0110;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static UPTIME_NANOS: AtomicU64 = AtomicU64::new(0);
+static NANOS_PER_TICK: AtomicU64 = AtomicU64::new(0);
+static FREQUENCY: AtomicU32 = AtomicU32::new(0);
+
+/// Called on every tick from the interrupt handler, see [`set_tick_hook`].
+static TICK_HOOK: IrqSpinLock<Option<fn()>> = IrqSpinLock::new(None);
+
+/// Maximum number of tasks that can wait for the next tick at once.
+const MAX_TICK_WAKERS: usize = 32;
+
+/// Wakers of the tasks waiting for the next tick. Only locked with interrupts
+/// disabled, so the interrupt handler never finds it locked.
+static TICK_WAKERS: spin::Mutex<heapless::Vec<Waker, MAX_TICK_WAKERS>> =
+    spin::Mutex::new(heapless::Vec::new());
+
+/// Programs the PIT to [`DEFAULT_FREQUENCY`] and unmasks IRQ 0.
+pub fn init() {
+    set_frequency(DEFAULT_FREQUENCY);
+    pic::unmask(InterruptIndex::Timer);
+}
+
+/// Programs channel 0 of the PIT to fire at (roughly) `hz` interrupts per
+/// second and returns the frequency actually achieved by the divisor.
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = (PIT_BASE_FREQUENCY / hz.max(1)).clamp(1, 65536);
+    // a reload value of 0 stands for 65536
+    let reload = (divisor & 0xffff) as u16;
+
+    interrupts::without_interrupts(|| {
+        let mut command: Port<u8> = Port::new(PIT_COMMAND);
+        let mut channel: Port<u8> = Port::new(PIT_CHANNEL_0);
+        unsafe {
+            command.write(PIT_MODE_SQUARE_WAVE);
+            channel.write(reload as u8);
+            channel.write((reload >> 8) as u8);
+        }
+        NANOS_PER_TICK.store(
+            u64::from(divisor) * NANOS_PER_SEC / u64::from(PIT_BASE_FREQUENCY),
+            Ordering::Relaxed,
+        );
+        FREQUENCY.store(PIT_BASE_FREQUENCY / divisor, Ordering::Relaxed);
+    });
+
+    frequency()
+}
+
+/// The current timer interrupt frequency in Hz, or 0 before [`init`].
+pub fn frequency() -> u32 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Called by the IRQ 0 handler.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    UPTIME_NANOS.fetch_add(NANOS_PER_TICK.load(Ordering::Relaxed), Ordering::Relaxed);
+    if let Some(mut wakers) = TICK_WAKERS.try_lock() {
+        while let Some(waker) = wakers.pop() {
+            waker.wake();
+        }
+    }
+    let hook = *TICK_HOOK.lock();
+    if let Some(hook) = hook {
+        hook();
+    }
+}
+
+/// Sets a function to run in interrupt context on every timer tick. It must
+/// not block.
+pub fn set_tick_hook(hook: Option<fn()>) {
+    *TICK_HOOK.lock() = hook;
+}
+
+/// Number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Time since the timer was started, with the resolution of one tick.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(UPTIME_NANOS.load(Ordering::Relaxed))
+}
+
+/// Halts the CPU until at least `duration` has passed.
+///
+/// Interrupts must be enabled, otherwise the timer never advances.
+pub fn sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+pub fn sleep_ms(ms: u64) {
+    sleep(Duration::from_millis(ms));
+}
+
+/// Busy-waits until at least `duration` has passed, for callers that must not
+/// halt (e.g. while polling a device).
+pub fn spin_sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns a future that completes once at least `duration` has passed.
+pub fn delay(duration: Duration) -> Delay {
+    Delay {
+        deadline: Instant::now() + duration,
+        slot: None,
+    }
+}
+
+/// A future that is checked again on every timer tick until its deadline.
+pub struct Delay {
+    deadline: Instant,
+    /// Index of this delay's waker in [`TICK_WAKERS`] and the tick it was
+    /// registered at. The slot is only valid until the next tick drains it.
+    slot: Option<(usize, u64)>,
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        if Instant::now() >= this.deadline {
+            return Poll::Ready(());
+        }
+        let registered = interrupts::without_interrupts(|| {
+            let mut wakers = TICK_WAKERS.lock();
+            let now = ticks();
+            match this.slot {
+                // polled again before the next tick, reuse the slot
+                Some((index, tick)) if tick == now => {
+                    if !wakers[index].will_wake(cx.waker()) {
+                        wakers[index] = cx.waker().clone();
+                    }
+                    true
+                }
+                _ => {
+                    let pushed = wakers.push(cx.waker().clone()).is_ok();
+                    if pushed {
+                        this.slot = Some((wakers.len() - 1, now));
+                    }
+                    pushed
+                }
+            }
+        });
+        if !registered {
+            // too many waiters, poll again without waiting for a tick
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+/// A point in time measured by the system timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(Duration);
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant(uptime())
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl core::ops::Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0 + rhs)
+    }
+}
+
+#[test_case]
+fn test_ticks_advance() {
+    let start = ticks();
+    sleep_ms(10);
+    assert!(ticks() > start);
+}
+
+#[test_case]
+fn test_sleep_duration() {
+    let start = Instant::now();
+    sleep(Duration::from_millis(20));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test_case]
+fn test_set_frequency() {
+    assert_eq!(
+        set_frequency(100),
+        PIT_BASE_FREQUENCY / (PIT_BASE_FREQUENCY / 100)
+    );
+    let start = Instant::now();
+    spin_sleep(Duration::from_millis(30));
+    assert!(start.elapsed() >= Duration::from_millis(30));
+    set_frequency(DEFAULT_FREQUENCY);
+}
@@ -1,8 +1,22 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use ansi::{Action, Erase, Parser};
 use core::fmt;
-use lazy_static::lazy_static;
-use spin;
 use volatile::Volatile;
 
+pub mod ansi;
+mod console;
+pub mod cp437;
+mod cursor;
+
+#[doc(hidden)]
+pub use console::_console_print;
+pub use console::{
+    active_console, console, switch_console, with_active_console, with_console, ConsoleId,
+    Consoles, CONSOLES, CONSOLE_COUNT,
+};
+pub use cursor::CursorShape;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -25,12 +39,53 @@ pub enum Color {
     White = 15,
 }
 
+/// The colors in the order of their values, which is also the order of the
+/// ANSI colors with blue and red swapped.
+const COLORS: [Color; 16] = [
+    Color::Black,
+    Color::Blue,
+    Color::Green,
+    Color::Cyan,
+    Color::Red,
+    Color::Magenta,
+    Color::Brown,
+    Color::LightGray,
+    Color::DarkGray,
+    Color::LightBlue,
+    Color::LightGreen,
+    Color::LightCyan,
+    Color::LightRed,
+    Color::Pink,
+    Color::Yellow,
+    Color::White,
+];
+
+impl Color {
+    /// Maps ANSI color `index` (0 to 7: black, red, green, yellow, blue,
+    /// magenta, cyan, white) to the dark or bright VGA color.
+    pub fn from_ansi(index: u8, bright: bool) -> Color {
+        // ANSI has red at bit 0 and blue at bit 2, VGA the other way around
+        let vga = (index & 0b010) | (index & 0b001) << 2 | (index & 0b100) >> 2;
+        COLORS[usize::from(vga) | if bright { 8 } else { 0 }]
+    }
+
+    /// The bright variant of a dark color.
+    pub fn bright(self) -> Color {
+        COLORS[self as usize | 8]
+    }
+
+    /// The dark variant of a bright color.
+    pub fn dark(self) -> Color {
+        COLORS[self as usize & 7]
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 struct ColorCode(u8);
 
 impl ColorCode {
-    fn new(text_color: Color, background: Color) -> ColorCode {
+    const fn new(text_color: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (text_color as u8))
     }
 }
@@ -45,6 +100,19 @@ struct ScreenChar {
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 
+/// Number of off-screen rows kept once [`set_scrollback_size`] is called.
+pub const DEFAULT_SCROLLBACK_LINES: usize = 200;
+/// The colors after reset, such as by `ESC [ 0 m`.
+pub const DEFAULT_COLORS: (Color, Color) = (Color::White, Color::Black);
+
+/// Distance of the tab stops.
+pub const TAB_WIDTH: usize = 8;
+
+/// Number of rows scrolled by Page Up and Page Down.
+pub const SCROLL_PAGE_LINES: usize = BUFFER_HEIGHT - 1;
+
+type Row = [ScreenChar; BUFFER_WIDTH];
+
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
@@ -53,17 +121,59 @@ struct Buffer {
 pub struct Writer {
     row: usize,
     col: usize,
-    text_color: Color,
-    background: Color,
+    rendition: Rendition,
     buffer: &'static mut Buffer,
+    scrollback: Scrollback,
+    parser: Parser,
+    saved_cursor: (usize, usize),
+    /// Number of rows used for text, from the top. The rows below are left
+    /// to the status bar.
+    height: usize,
+    /// Whether `buffer` is the screen.
+    visible: bool,
+    cursor_shape: CursorShape,
+}
+
+/// Rows that were scrolled off the top of the screen.
+struct Scrollback {
+    /// Oldest first. The capacity is reserved up front, so pushing never
+    /// allocates.
+    rows: VecDeque<Row>,
+    limit: usize,
+    /// The live screen while the view is scrolled back, `None` if no
+    /// scrollback is kept.
+    live: Option<Box<[Row; BUFFER_HEIGHT]>>,
+    /// Number of rows the view is scrolled back, 0 for the live view.
+    offset: usize,
 }
 
 impl Writer {
+    fn new(buffer: &'static mut Buffer, visible: bool) -> Writer {
+        Writer {
+            row: 0,
+            col: 0,
+            rendition: Rendition::new(),
+            buffer,
+            scrollback: Scrollback {
+                rows: VecDeque::new(),
+                limit: 0,
+                live: None,
+                offset: 0,
+            },
+            parser: Parser::new(),
+            saved_cursor: (0, 0),
+            height: BUFFER_HEIGHT,
+            visible,
+            cursor_shape: CursorShape::Underline,
+        }
+    }
+
     pub fn write_byte(&mut self, byte: u8) {
-        self.write_colored_byte(byte, self.text_color, self.background)
+        self.write_colored_byte(byte, self.rendition.text_color, self.rendition.background)
     }
 
     pub fn write_colored_byte(&mut self, byte: u8, text_color: Color, background: Color) {
+        self.show_live();
         match byte {
             b'\n' => self.new_line(),
             byte => {
@@ -80,20 +190,64 @@ impl Writer {
         }
     }
 
+    /// Writes a string, interpreting ANSI escape sequences. Characters
+    /// outside ASCII are drawn with their code page 437 glyph.
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // anything else is not part of printable ASCII range
-                _ => {
-                    // make the text color red if it is not valid ascii, 
-                    // but if the background is red, make the text yellow
-                    if self.background == Color::Red {
-                        self.write_colored_byte(0xfe, Color::Yellow, self.background);
-                    } else {
-                        self.write_colored_byte(0xfe, Color::Red, self.background);
-                    }
+        for c in s.chars() {
+            if !c.is_ascii() {
+                self.print_char(c);
+            } else if let Some(action) = self.parser.advance(c as u8) {
+                self.apply(action);
+            }
+        }
+        self.update_cursor();
+    }
+
+    /// Prints a character without moving the hardware cursor, which the
+    /// callers update once per string.
+    fn print_char(&mut self, c: char) {
+        match c {
+            '\r' => {
+                self.show_live();
+                self.col = 0;
+                return;
+            }
+            '\t' => {
+                self.show_live();
+                let col = self.col.min(BUFFER_WIDTH - 1);
+                self.col = ((col / TAB_WIDTH + 1) * TAB_WIDTH).min(BUFFER_WIDTH - 1);
+                return;
+            }
+            // backspace erases the character before the cursor, but does
+            // not go back to the previous line
+            '\x08' => {
+                self.show_live();
+                self.col = self.col.saturating_sub(1);
+                self.clear_cols(self.row, self.col..self.col + 1);
+                return;
+            }
+            // form feed
+            '\x0c' => {
+                self.clear_text();
+                return;
+            }
+            // bell, there is no speaker to ring
+            '\x07' => return,
+            _ => {}
+        }
+        match cp437::from_char(c) {
+            // printable ASCII byte or newline
+            Some(byte @ (0x20..=0x7e | b'\n')) => self.write_byte(byte),
+            // glyphs outside ASCII
+            Some(byte) if !c.is_ascii() => self.write_byte(byte),
+            // control character or no glyph for it
+            _ => {
+                // make the text color red if it is not valid ascii,
+                // but if the background is red, make the text yellow
+                if self.rendition.background == Color::Red {
+                    self.write_colored_byte(0xfe, Color::Yellow, self.rendition.background);
+                } else {
+                    self.write_colored_byte(0xfe, Color::Red, self.rendition.background);
                 }
             }
         }
@@ -109,17 +263,218 @@ impl Writer {
                 _ => self.write_colored_byte(byte, text_color, background),
             }
         }
+        self.update_cursor();
     }
 
-    fn new_line(&mut self) {
-        if self.row == BUFFER_HEIGHT - 1 {
-            for row in 1..BUFFER_HEIGHT {
-                for col in 0..BUFFER_WIDTH {
-                    let character = self.buffer.chars[row][col].read();
-                    self.buffer.chars[row - 1][col].write(character);
+    /// Blanks the screen and moves the cursor to the top left.
+    pub fn clear_screen(&mut self) {
+        self.clear_text();
+        self.update_cursor();
+    }
+
+    /// Like [`Writer::clear_screen`], but leaves the hardware cursor to the
+    /// caller, which updates it once it is done writing.
+    fn clear_text(&mut self) {
+        self.show_live();
+        for row in 0..self.height {
+            self.clear_row(row);
+        }
+        self.row = 0;
+        self.col = 0;
+    }
+
+    /// Row and column of the cursor.
+    pub fn cursor_position(&self) -> (usize, usize) {
+        (self.row, self.col.min(BUFFER_WIDTH - 1))
+    }
+
+    /// Sets the shape of this console's hardware cursor. It takes effect
+    /// right away if the console is shown, otherwise once it is switched to.
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) {
+        self.cursor_shape = shape;
+        if self.visible {
+            cursor::set_shape(shape);
+        }
+    }
+
+    /// Sets the shape and position of the hardware cursor after the console
+    /// was switched to.
+    fn show_cursor(&self) {
+        cursor::set_shape(self.cursor_shape);
+        self.update_cursor();
+    }
+
+    /// Moves the hardware cursor to the cursor position, or off the screen
+    /// while the view is scrolled back. Does nothing if the console is not
+    /// shown.
+    fn update_cursor(&self) {
+        if !self.visible {
+            return;
+        }
+        let cell = if self.scrollback.offset > 0 {
+            BUFFER_HEIGHT * BUFFER_WIDTH
+        } else {
+            let (row, col) = self.cursor_position();
+            row * BUFFER_WIDTH + col
+        };
+        cursor::set_position(cell as u16);
+    }
+
+    fn apply(&mut self, action: Action) {
+        self.show_live();
+        // the column is one past the last while a wrap is pending
+        let col = self.col.min(BUFFER_WIDTH - 1);
+        match action {
+            Action::Print(byte) => self.print_char(char::from(byte)),
+            Action::Sgr(params) => {
+                for &param in &params {
+                    self.rendition.apply(param);
+                }
+            }
+            Action::CursorUp(n) => self.row = self.row.saturating_sub(n),
+            Action::CursorDown(n) => self.row = (self.row + n).min(self.height - 1),
+            Action::CursorForward(n) => self.col = (col + n).min(BUFFER_WIDTH - 1),
+            Action::CursorBack(n) => self.col = col.saturating_sub(n),
+            Action::CursorColumn(col) => self.col = col.min(BUFFER_WIDTH - 1),
+            Action::CursorPosition { row, col } => {
+                self.row = row.min(self.height - 1);
+                self.col = col.min(BUFFER_WIDTH - 1);
+            }
+            Action::EraseInLine(erase) => {
+                let cols = match erase {
+                    Erase::ToEnd => col..BUFFER_WIDTH,
+                    Erase::ToStart => 0..col + 1,
+                    Erase::All | Erase::AllAndHistory => 0..BUFFER_WIDTH,
+                };
+                self.clear_cols(self.row, cols);
+            }
+            Action::EraseInDisplay(erase) => {
+                let rows = match erase {
+                    Erase::ToEnd => {
+                        self.clear_cols(self.row, col..BUFFER_WIDTH);
+                        self.row + 1..self.height
+                    }
+                    Erase::ToStart => {
+                        self.clear_cols(self.row, 0..col + 1);
+                        0..self.row
+                    }
+                    Erase::All => 0..self.height,
+                    Erase::AllAndHistory => {
+                        self.scrollback.rows.clear();
+                        0..self.height
+                    }
+                };
+                for row in rows {
+                    self.clear_row(row);
+                }
+            }
+            Action::SaveCursor => self.saved_cursor = (self.row, self.col),
+            Action::RestoreCursor => (self.row, self.col) = self.saved_cursor,
+        }
+    }
+
+    /// The colors used by [`Writer::write_string`].
+    pub fn color(&self) -> (Color, Color) {
+        (self.rendition.text_color, self.rendition.background)
+    }
+
+    pub fn set_color(&mut self, text_color: Color, background: Color) {
+        self.rendition.set_color(text_color, background);
+    }
+
+    /// Scrolls the view back by up to `lines` rows of history.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let history = self.scrollback.rows.len();
+        let live = match &mut self.scrollback.live {
+            Some(live) => live,
+            None => return,
+        };
+        if self.scrollback.offset == 0 {
+            for (row, saved) in live.iter_mut().enumerate().take(self.height) {
+                for (col, character) in saved.iter_mut().enumerate() {
+                    *character = self.buffer.chars[row][col].read();
                 }
             }
-            self.clear_row(BUFFER_HEIGHT - 1);
+        }
+        self.scrollback.offset = (self.scrollback.offset + lines).min(history);
+        self.show_view();
+        self.update_cursor();
+    }
+
+    /// Scrolls the view forward by `lines` rows, at most to the live view.
+    pub fn scroll_down(&mut self, lines: usize) {
+        if self.scrollback.offset == 0 {
+            return;
+        }
+        if lines >= self.scrollback.offset {
+            self.show_live();
+        } else {
+            self.scrollback.offset -= lines;
+            self.show_view();
+        }
+        self.update_cursor();
+    }
+
+    /// Number of rows the view is scrolled back, 0 for the live view.
+    pub fn view_offset(&self) -> usize {
+        self.scrollback.offset
+    }
+
+    /// Returns to the live view if the view is scrolled back.
+    pub fn show_live(&mut self) {
+        if self.scrollback.offset == 0 {
+            return;
+        }
+        if let Some(live) = &self.scrollback.live {
+            for (row, saved) in live.iter().enumerate().take(self.height) {
+                for (col, &character) in saved.iter().enumerate() {
+                    self.buffer.chars[row][col].write(character);
+                }
+            }
+        }
+        self.scrollback.offset = 0;
+    }
+
+    /// Draws the rows `offset` rows above the live screen.
+    fn show_view(&mut self) {
+        let Scrollback {
+            rows, live, offset, ..
+        } = &self.scrollback;
+        let live = match live {
+            Some(live) => live,
+            None => return,
+        };
+        let first = rows.len() - offset;
+        let text_rows = self.buffer.chars.iter_mut().take(self.height);
+        for (row, screen_row) in text_rows.enumerate() {
+            let line = first + row;
+            let source = match rows.get(line) {
+                Some(source) => source,
+                None => &live[line - rows.len()],
+            };
+            for (screen_char, &character) in screen_row.iter_mut().zip(source) {
+                screen_char.write(character);
+            }
+        }
+    }
+
+    fn save_top_row(&mut self) {
+        let scrollback = &mut self.scrollback;
+        if scrollback.limit == 0 {
+            return;
+        }
+        if scrollback.rows.len() == scrollback.limit {
+            scrollback.rows.pop_front();
+        }
+        let top = &self.buffer.chars[0];
+        scrollback
+            .rows
+            .push_back(core::array::from_fn(|col| top[col].read()));
+    }
+
+    fn new_line(&mut self) {
+        if self.row == self.height - 1 {
+            self.scroll_text();
             // when scrolling, down, the writer remains on the bottom row
         } else {
             self.row += 1;
@@ -127,17 +482,120 @@ impl Writer {
         self.col = 0;
     }
 
+    /// Moves the text rows up by one, saving the top row to the history.
+    fn scroll_text(&mut self) {
+        self.save_top_row();
+        for row in 1..self.height {
+            for col in 0..BUFFER_WIDTH {
+                let character = self.buffer.chars[row][col].read();
+                self.buffer.chars[row - 1][col].write(character);
+            }
+        }
+        self.clear_row(self.height - 1);
+    }
+
+    /// Changes the number of text rows, scrolling the text up if the cursor
+    /// would be below them. Rows that are given up are not cleared.
+    fn set_height(&mut self, height: usize) {
+        self.show_live();
+        while self.row >= height {
+            self.scroll_text();
+            self.row -= 1;
+        }
+        let new_rows = self.height..height;
+        self.height = height;
+        for row in new_rows {
+            self.clear_row(row);
+        }
+        self.saved_cursor.0 = self.saved_cursor.0.min(height - 1);
+        self.update_cursor();
+    }
+
     fn clear_row(&mut self, row: usize) {
+        self.clear_cols(row, 0..BUFFER_WIDTH);
+    }
+
+    fn clear_cols(&mut self, row: usize, cols: core::ops::Range<usize>) {
         let blank = ScreenChar {
             ascii_char: b' ',
-            color: ColorCode::new(self.text_color, self.background),
+            color: ColorCode::new(self.rendition.text_color, self.rendition.background),
         };
-        for col in 0..BUFFER_WIDTH {
+        for col in cols {
             self.buffer.chars[row][col].write(blank);
         }
     }
 }
 
+/// The colors of a console as set by SGR escape sequences, shared by the VGA
+/// and the framebuffer consoles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rendition {
+    pub text_color: Color,
+    pub background: Color,
+    /// The text color without the brightening while bold is on, so that
+    /// turning bold off restores exactly that color.
+    bold: Option<Color>,
+}
+
+impl Rendition {
+    /// The default colors, without bold.
+    pub const fn new() -> Rendition {
+        Rendition {
+            text_color: DEFAULT_COLORS.0,
+            background: DEFAULT_COLORS.1,
+            bold: None,
+        }
+    }
+
+    /// Sets both colors, leaving bold as it is.
+    pub fn set_color(&mut self, text_color: Color, background: Color) {
+        self.text_color = text_color;
+        self.background = background;
+    }
+
+    /// Applies the SGR parameter `param`.
+    pub fn apply(&mut self, param: u16) {
+        match param {
+            0 => *self = Rendition::new(),
+            1 => {
+                if self.bold.is_none() {
+                    self.bold = Some(self.text_color);
+                    self.text_color = self.text_color.bright();
+                }
+            }
+            22 => {
+                if let Some(base) = self.bold.take() {
+                    self.text_color = base;
+                }
+            }
+            30..=37 => self.set_text_color(Color::from_ansi((param - 30) as u8, false)),
+            39 => self.set_text_color(DEFAULT_COLORS.0),
+            40..=47 => self.background = Color::from_ansi((param - 40) as u8, false),
+            49 => self.background = DEFAULT_COLORS.1,
+            90..=97 => self.set_text_color(Color::from_ansi((param - 90) as u8, true)),
+            100..=107 => self.background = Color::from_ansi((param - 100) as u8, true),
+            _ => {}
+        }
+    }
+
+    /// Sets the text color, brightened while bold is on.
+    fn set_text_color(&mut self, color: Color) {
+        match &mut self.bold {
+            Some(base) => {
+                *base = color;
+                self.text_color = color.bright();
+            }
+            None => self.text_color = color,
+        }
+    }
+}
+
+impl Default for Rendition {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.write_string(s);
@@ -145,14 +603,31 @@ impl fmt::Write for Writer {
     }
 }
 
-lazy_static! {
-    pub static ref WRITER: spin::Mutex<Writer> = spin::Mutex::new(Writer {
-        row: 0,
-        col: 0,
-        text_color: Color::White,
-        background: Color::Black,
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+/// Keeps up to `lines` rows that scroll off the screen, so that they can be
+/// viewed with [`Writer::scroll_up`]. Requires the heap; no rows are kept
+/// before this is called.
+pub fn set_scrollback_size(lines: usize) {
+    let blank = ScreenChar {
+        ascii_char: b' ',
+        color: ColorCode::new(Color::White, Color::Black),
+    };
+    let mut histories: [Scrollback; CONSOLE_COUNT] = core::array::from_fn(|_| Scrollback {
+        rows: VecDeque::with_capacity(lines),
+        limit: lines,
+        live: (lines > 0).then(|| Box::new([[blank; BUFFER_WIDTH]; BUFFER_HEIGHT])),
+        offset: 0,
     });
+    // swap the new histories in, so that neither allocating nor freeing
+    // happens with the consoles locked
+    let mut consoles = CONSOLES.lock();
+    for (writer, scrollback) in consoles.writers_mut().zip(&mut histories) {
+        writer.show_live();
+        let old = &mut writer.scrollback.rows;
+        let kept = old.len().min(lines);
+        scrollback.rows.extend(old.drain(old.len() - kept..));
+        core::mem::swap(&mut writer.scrollback, scrollback);
+    }
+    drop(consoles);
 }
 
 #[macro_export]
@@ -166,10 +641,84 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+/// Prints to the framebuffer console once graphics mode is on, otherwise to
+/// the main console.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    if crate::framebuffer::_print(args) {
+        return;
+    }
+    // interrupts stay disabled while the consoles are locked, so a handler
+    // that prints cannot deadlock on them
+    _console_print(ConsoleId::MAIN, args);
+}
+
+/// Text output with changeable colors, i.e. a VGA console or the framebuffer
+/// console.
+pub trait ColorWrite: fmt::Write {
+    fn color(&self) -> (Color, Color);
+    fn set_color(&mut self, text_color: Color, background: Color);
+}
+
+impl ColorWrite for Writer {
+    fn color(&self) -> (Color, Color) {
+        Writer::color(self)
+    }
+
+    fn set_color(&mut self, text_color: Color, background: Color) {
+        Writer::set_color(self, text_color, background)
+    }
+}
+
+/// Runs `f` with the output [`_print`] writes to locked, for output that must
+/// not be interleaved with other prints.
+pub fn with_writer<R>(f: impl FnOnce(&mut dyn ColorWrite) -> R) -> R {
+    if crate::framebuffer::is_enabled() {
+        // graphics mode is never left again
+        crate::framebuffer::with_console(|console| f(console)).unwrap()
+    } else {
+        with_console(ConsoleId::MAIN, |writer| f(writer))
+    }
+}
+
+/// Prints like [`_print`] if the console is not locked, without waiting for
+/// it otherwise. Returns whether the output was printed.
+pub fn try_print(args: fmt::Arguments) -> bool {
     use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+    match crate::framebuffer::try_with_console(|console| console.write_fmt(args).unwrap()) {
+        Some(Some(())) => return true,
+        Some(None) => {}
+        None => return false,
+    }
+    match CONSOLES.try_lock() {
+        Some(mut consoles) => {
+            consoles.get_mut(ConsoleId::MAIN).write_fmt(args).unwrap();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Prints like [`_print`], showing the main console, even if the console is
+/// locked, for panic and exception context where the holder may never unlock
+/// it again. The output can end up in the middle of the interrupted print.
+pub fn force_print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let printed = crate::framebuffer::force_with_console(|console| {
+        console.write_fmt(args).unwrap();
+    });
+    if printed.is_some() {
+        return;
+    }
+    let mut consoles = match CONSOLES.try_lock() {
+        Some(consoles) => consoles,
+        None => {
+            unsafe { CONSOLES.force_unlock() };
+            CONSOLES.lock()
+        }
+    };
+    consoles.switch_to(ConsoleId::MAIN);
+    consoles.active_mut().write_fmt(args).unwrap();
 }
 
 #[test_case]
@@ -193,7 +742,168 @@ fn test_println_output() {
     let s = "Some test string that fits on a single line";
     println!("{}", s);
     for (i, c) in s.chars().enumerate() {
-        let screen_char = WRITER.lock().buffer.chars[BUFFER_HEIGHT - 2][i].read();
+        let screen_char =
+            CONSOLES.lock().get_mut(ConsoleId::MAIN).buffer.chars[BUFFER_HEIGHT - 2][i].read();
         assert_eq!(char::from(screen_char.ascii_char), c);
     }
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_scrollback() {
+    use core::fmt::Write;
+
+    fn row_text(writer: &Writer, row: usize) -> ScreenText {
+        let mut text = screen_text(writer, row, 0..BUFFER_WIDTH);
+        while text.ends_with(' ') {
+            text.pop();
+        }
+        text
+    }
+
+    let mut consoles = CONSOLES.lock();
+    let writer = consoles.get_mut(ConsoleId::MAIN);
+    for i in 0..BUFFER_HEIGHT * 2 {
+        writeln!(writer, "scrollback line {}", i).unwrap();
+    }
+    // the newest line is above the empty cursor row
+    let newest = BUFFER_HEIGHT - 2;
+    assert_eq!(row_text(writer, newest), "scrollback line 49");
+
+    writer.scroll_up(3);
+    assert_eq!(writer.view_offset(), 3);
+    assert_eq!(row_text(writer, newest), "scrollback line 46");
+    writer.scroll_down(1);
+    assert_eq!(writer.view_offset(), 2);
+    assert_eq!(row_text(writer, newest), "scrollback line 47");
+    assert_eq!(row_text(writer, 0), "scrollback line 24");
+
+    // new output snaps back to the live view
+    writer.write_string("x");
+    assert_eq!(writer.view_offset(), 0);
+    assert_eq!(row_text(writer, newest), "scrollback line 49");
+    assert_eq!(row_text(writer, BUFFER_HEIGHT - 1), "x");
+    writer.write_string("\n");
+}
+
+#[cfg(test)]
+fn screen_char(writer: &Writer, row: usize, col: usize) -> (char, ColorCode) {
+    let screen_char = writer.buffer.chars[row][col].read();
+    (char::from(screen_char.ascii_char), screen_char.color)
+}
+
+/// The text of a screen row, room for two UTF-8 bytes per column.
+#[cfg(test)]
+type ScreenText = heapless::String<{ BUFFER_WIDTH * 2 }>;
+
+/// Reads screen text without allocating, so it can be used while the console
+/// lock is held.
+#[cfg(test)]
+fn screen_text(writer: &Writer, row: usize, cols: core::ops::Range<usize>) -> ScreenText {
+    let mut text = ScreenText::new();
+    for col in cols {
+        text.push(screen_char(writer, row, col).0).unwrap();
+    }
+    text
+}
+
+#[test_case]
+fn test_ansi_colors() {
+    let mut consoles = CONSOLES.lock();
+    let writer = consoles.get_mut(ConsoleId::MAIN);
+    writer.write_string("\x1b[2J\x1b[H\x1b[31mr\x1b[1mR\x1b[44mb\x1b[22mn\x1b[0md");
+    let expected = [
+        ('r', Color::Red, Color::Black),
+        ('R', Color::LightRed, Color::Black),
+        ('b', Color::LightRed, Color::Blue),
+        ('n', Color::Red, Color::Blue),
+        ('d', Color::White, Color::Black),
+    ];
+    for (col, &(c, text_color, background)) in expected.iter().enumerate() {
+        assert_eq!(
+            screen_char(writer, 0, col),
+            (c, ColorCode::new(text_color, background))
+        );
+    }
+    assert_eq!(writer.color(), DEFAULT_COLORS);
+
+    // turning bold off only undoes its own brightening
+    writer.write_string("\x1b[1m\x1b[22m");
+    assert_eq!(writer.color(), DEFAULT_COLORS);
+    writer.write_string("\x1b[93m\x1b[1m\x1b[22m");
+    assert_eq!(writer.color().0, Color::Yellow);
+    writer.write_string("\x1b[1m\x1b[32m\x1b[22m");
+    assert_eq!(writer.color().0, Color::Green);
+    writer.write_string("\x1b[0m\x1b[25;1H");
+}
+
+#[test_case]
+fn test_ansi_cursor_and_erase() {
+    let mut consoles = CONSOLES.lock();
+    let writer = consoles.get_mut(ConsoleId::MAIN);
+    writer.write_string("\x1b[2J\x1b[Habcdef\x1b[3D\x1b[K");
+    assert_eq!(screen_text(writer, 0, 0..6), "abc   ");
+
+    writer.write_string("\x1b[5;10Hxy\x1b[s\x1b[1;1H\x1b[uz\x1b[2A\x1b[4Cw");
+    assert_eq!(screen_text(writer, 4, 9..12), "xyz");
+    assert_eq!(screen_text(writer, 2, 16..17), "w");
+
+    writer.write_string("\x1b[5;11H\x1b[1K");
+    assert_eq!(screen_text(writer, 4, 9..12), "  z");
+
+    writer.write_string("\x1b[1;2H\x1b[J");
+    assert_eq!(screen_text(writer, 0, 0..3), "a  ");
+    assert_eq!(screen_text(writer, 2, 16..17), " ");
+    assert_eq!(screen_text(writer, 4, 11..12), " ");
+    writer.write_string("\x1b[25;1H");
+}
+
+#[test_case]
+fn test_utf8_to_cp437() {
+    let mut consoles = CONSOLES.lock();
+    let writer = consoles.get_mut(ConsoleId::MAIN);
+    writer.write_string("\x1b[2J\x1b[Hé╔π日\x01a");
+    let expected = [
+        (0x82, Color::White),
+        (0xc9, Color::White),
+        (0xe3, Color::White),
+        // one replacement glyph per unmappable code point
+        (0xfe, Color::Red),
+        (0xfe, Color::Red),
+        (b'a', Color::White),
+    ];
+    for (col, &(byte, text_color)) in expected.iter().enumerate() {
+        assert_eq!(
+            screen_char(writer, 0, col),
+            (char::from(byte), ColorCode::new(text_color, Color::Black))
+        );
+    }
+    writer.write_string("\x1b[25;1H");
+}
+
+#[test_case]
+fn test_control_characters() {
+    let mut consoles = CONSOLES.lock();
+    let writer = consoles.get_mut(ConsoleId::MAIN);
+    writer.write_string("\x0cabc\rx\tt\x07");
+    assert_eq!(screen_char(writer, 0, 0).0, 'x');
+    assert_eq!(screen_char(writer, 0, 1).0, 'b');
+    assert_eq!(screen_char(writer, 0, TAB_WIDTH).0, 't');
+    assert_eq!(writer.cursor_position(), (0, TAB_WIDTH + 1));
+
+    writer.write_string("\x08\x08");
+    assert_eq!(screen_char(writer, 0, TAB_WIDTH).0, ' ');
+    assert_eq!(writer.cursor_position(), (0, TAB_WIDTH - 1));
+    writer.write_string("\n\x08\t\t");
+    assert_eq!(writer.cursor_position(), (1, 2 * TAB_WIDTH));
+
+    // the hardware cursor follows the writer
+    assert_eq!(
+        usize::from(cursor::position()),
+        BUFFER_WIDTH + 2 * TAB_WIDTH
+    );
+    writer.write_string("\x0c");
+    assert_eq!(writer.cursor_position(), (0, 0));
+    assert_eq!(cursor::position(), 0);
+    assert_eq!(screen_char(writer, 0, 0).0, ' ');
+    writer.write_string("\x1b[25;1H");
+}
@@ -0,0 +1,238 @@
+//! A parser for the subset of VT100/ANSI escape sequences the VGA writer
+//! understands.
+
+/// Maximum number of numeric parameters of a control sequence; further ones
+/// are ignored.
+pub const MAX_PARAMS: usize = 16;
+
+const ESC: u8 = 0x1b;
+/// Cancel and substitute abort a sequence.
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+
+pub type Params = heapless::Vec<u16, MAX_PARAMS>;
+
+/// What the writer should do after a byte was fed to the [`Parser`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// A byte that is not part of an escape sequence.
+    Print(u8),
+    /// Select Graphic Rendition, with at least one parameter.
+    Sgr(Params),
+    CursorUp(usize),
+    CursorDown(usize),
+    CursorForward(usize),
+    CursorBack(usize),
+    /// Zero-based.
+    CursorColumn(usize),
+    /// Zero-based.
+    CursorPosition {
+        row: usize,
+        col: usize,
+    },
+    EraseInLine(Erase),
+    EraseInDisplay(Erase),
+    SaveCursor,
+    RestoreCursor,
+}
+
+/// Which part of a line or the screen to erase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Erase {
+    ToEnd,
+    ToStart,
+    All,
+    /// The whole screen and the scrollback history.
+    AllAndHistory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    /// Consumes an unsupported control sequence up to its final byte.
+    IgnoreCsi,
+}
+
+pub struct Parser {
+    state: State,
+    params: Params,
+    /// Value of the parameter being read, `None` if it has no digits yet.
+    current: Option<u16>,
+    /// Set by a `?` or other private marker, which none of the supported
+    /// sequences have.
+    private: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: heapless::Vec::new(),
+            current: None,
+            private: false,
+        }
+    }
+
+    pub fn advance(&mut self, byte: u8) -> Option<Action> {
+        match (self.state, byte) {
+            (_, CAN) | (_, SUB) if self.state != State::Ground => {
+                self.state = State::Ground;
+                None
+            }
+            (_, ESC) => {
+                self.state = State::Escape;
+                None
+            }
+            (State::Ground, byte) => Some(Action::Print(byte)),
+            (State::Escape, b'[') => {
+                self.state = State::Csi;
+                self.params.clear();
+                self.current = None;
+                self.private = false;
+                None
+            }
+            (State::Escape, byte) => {
+                self.state = State::Ground;
+                match byte {
+                    b'7' => Some(Action::SaveCursor),
+                    b'8' => Some(Action::RestoreCursor),
+                    _ => None,
+                }
+            }
+            (State::Csi, b'0'..=b'9') => {
+                let digit = u16::from(byte - b'0');
+                let value = self.current.unwrap_or(0);
+                self.current = Some(value.saturating_mul(10).saturating_add(digit));
+                None
+            }
+            (State::Csi, b';') => {
+                self.finish_param();
+                None
+            }
+            (State::Csi, b'<'..=b'?') => {
+                self.private = true;
+                None
+            }
+            (State::Csi, 0x40..=0x7e) => {
+                self.finish_param();
+                self.state = State::Ground;
+                if self.private {
+                    None
+                } else {
+                    self.dispatch(byte)
+                }
+            }
+            (State::Csi, 0x20..=0x2f) => {
+                // no supported sequence has intermediate bytes
+                self.state = State::IgnoreCsi;
+                None
+            }
+            (State::IgnoreCsi, 0x40..=0x7e) => {
+                self.state = State::Ground;
+                None
+            }
+            // other control characters inside a sequence are ignored
+            (State::Csi, _) | (State::IgnoreCsi, _) => None,
+        }
+    }
+
+    fn finish_param(&mut self) {
+        let value = self.current.take().unwrap_or(0);
+        let _ = self.params.push(value);
+    }
+
+    /// Parameter `index`, with 0 or a missing parameter meaning `default`.
+    fn param(&self, index: usize, default: u16) -> usize {
+        match self.params.get(index) {
+            Some(&value) if value != 0 => usize::from(value),
+            _ => usize::from(default),
+        }
+    }
+
+    fn erase_mode(&self) -> Option<Erase> {
+        match self.params.first().copied().unwrap_or(0) {
+            0 => Some(Erase::ToEnd),
+            1 => Some(Erase::ToStart),
+            2 => Some(Erase::All),
+            3 => Some(Erase::AllAndHistory),
+            _ => None,
+        }
+    }
+
+    fn dispatch(&mut self, final_byte: u8) -> Option<Action> {
+        let action = match final_byte {
+            b'm' => Action::Sgr(core::mem::take(&mut self.params)),
+            b'A' => Action::CursorUp(self.param(0, 1)),
+            b'B' => Action::CursorDown(self.param(0, 1)),
+            b'C' => Action::CursorForward(self.param(0, 1)),
+            b'D' => Action::CursorBack(self.param(0, 1)),
+            b'G' => Action::CursorColumn(self.param(0, 1) - 1),
+            b'H' | b'f' => Action::CursorPosition {
+                row: self.param(0, 1) - 1,
+                col: self.param(1, 1) - 1,
+            },
+            b'K' => Action::EraseInLine(self.erase_mode()?),
+            b'J' => Action::EraseInDisplay(self.erase_mode()?),
+            b's' => Action::SaveCursor,
+            b'u' => Action::RestoreCursor,
+            _ => return None,
+        };
+        Some(action)
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+fn parse(bytes: &[u8]) -> heapless::Vec<Action, 16> {
+    let mut parser = Parser::new();
+    bytes
+        .iter()
+        .filter_map(|&byte| parser.advance(byte))
+        .collect()
+}
+
+#[test_case]
+fn test_parse_sequences() {
+    let params = |values: &[u16]| Params::from_slice(values).unwrap();
+    assert_eq!(
+        parse(b"a\x1b[1;31mb\x1b[m"),
+        [
+            Action::Print(b'a'),
+            Action::Sgr(params(&[1, 31])),
+            Action::Print(b'b'),
+            Action::Sgr(params(&[0])),
+        ]
+    );
+    assert_eq!(
+        parse(b"\x1b[A\x1b[3B\x1b[0C\x1b[12;40H\x1b[;5f\x1b[2K\x1bM\x1b7\x1b[u"),
+        [
+            Action::CursorUp(1),
+            Action::CursorDown(3),
+            Action::CursorForward(1),
+            Action::CursorPosition { row: 11, col: 39 },
+            Action::CursorPosition { row: 0, col: 4 },
+            Action::EraseInLine(Erase::All),
+            Action::SaveCursor,
+            Action::RestoreCursor,
+        ]
+    );
+}
+
+#[test_case]
+fn test_unsupported_sequences_are_swallowed() {
+    assert_eq!(
+        parse(b"\x1b[?25lx\x1b[1 qy\x1b[5\x18z\x1b[9J"),
+        [
+            Action::Print(b'x'),
+            Action::Print(b'y'),
+            Action::Print(b'z')
+        ]
+    );
+}
@@ -0,0 +1,300 @@
+//! Virtual consoles: several writers of which one is shown on the screen.
+
+use super::{
+    cp437, Buffer, Color, ColorCode, Row, ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH,
+};
+use crate::sync::IrqSpinLock;
+use core::fmt;
+use core::ptr::addr_of_mut;
+use lazy_static::lazy_static;
+use volatile::Volatile;
+
+pub const CONSOLE_COUNT: usize = 6;
+
+const BLANK: ScreenChar = ScreenChar {
+    ascii_char: b' ',
+    color: ColorCode::new(Color::White, Color::Black),
+};
+
+/// Where the consoles that are not shown write to. Only the writer of console
+/// `i` accesses `BACKING[i]`, and only while it is not shown.
+static mut BACKING: [[Row; BUFFER_HEIGHT]; CONSOLE_COUNT] =
+    [[[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT]; CONSOLE_COUNT];
+
+lazy_static! {
+    pub static ref CONSOLES: IrqSpinLock<Consoles> = IrqSpinLock::new(Consoles {
+        writers: core::array::from_fn(|index| {
+            let shown = index == ConsoleId::MAIN.0;
+            let buffer = if shown {
+                unsafe { screen() }
+            } else {
+                unsafe { backing(index) }
+            };
+            Writer::new(buffer, shown)
+        }),
+        names: core::array::from_fn(|index| (index == ConsoleId::MAIN.0).then_some("main")),
+        active: ConsoleId::MAIN,
+        status_bar: false,
+    });
+}
+
+/// The VGA text buffer.
+///
+/// # Safety
+///
+/// Only the writer of the shown console may hold it.
+unsafe fn screen() -> &'static mut Buffer {
+    &mut *(0xb8000 as *mut Buffer)
+}
+
+/// The off-screen buffer of a console.
+///
+/// # Safety
+///
+/// Only the writer of the console may hold it, and only while the console
+/// is not shown.
+unsafe fn backing(index: usize) -> &'static mut Buffer {
+    &mut *(addr_of_mut!(BACKING[index]) as *mut Buffer)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConsoleId(usize);
+
+impl ConsoleId {
+    /// The console `print!` writes to, shown at boot.
+    pub const MAIN: ConsoleId = ConsoleId(0);
+
+    pub fn new(index: usize) -> Option<ConsoleId> {
+        (index < CONSOLE_COUNT).then_some(ConsoleId(index))
+    }
+
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+pub struct Consoles {
+    writers: [Writer; CONSOLE_COUNT],
+    names: [Option<&'static str>; CONSOLE_COUNT],
+    active: ConsoleId,
+    /// Whether the bottom row of the screen is reserved for the status bar.
+    status_bar: bool,
+}
+
+impl Consoles {
+    pub fn get_mut(&mut self, id: ConsoleId) -> &mut Writer {
+        &mut self.writers[id.0]
+    }
+
+    /// The writer of the shown console.
+    pub fn active_mut(&mut self) -> &mut Writer {
+        &mut self.writers[self.active.0]
+    }
+
+    pub fn active(&self) -> ConsoleId {
+        self.active
+    }
+
+    pub fn writers_mut(&mut self) -> impl Iterator<Item = &mut Writer> {
+        self.writers.iter_mut()
+    }
+
+    /// The console called `name`. If there is none, the first console
+    /// without a name gets it, unless all have one.
+    pub fn named(&mut self, name: &'static str) -> Option<ConsoleId> {
+        let index = match self.names.iter().position(|&n| n == Some(name)) {
+            Some(index) => index,
+            None => {
+                let index = self.names.iter().position(Option::is_none)?;
+                self.names[index] = Some(name);
+                index
+            }
+        };
+        Some(ConsoleId(index))
+    }
+
+    pub fn name(&self, id: ConsoleId) -> Option<&'static str> {
+        self.names[id.0]
+    }
+
+    /// Shows another console, saving the contents of the screen to the
+    /// buffer of the current one.
+    pub fn switch_to(&mut self, id: ConsoleId) {
+        if id == self.active {
+            return;
+        }
+        let old = &mut self.writers[self.active.0];
+        old.show_live();
+        let backing = unsafe { backing(self.active.0) };
+        copy_buffer(old.buffer, backing, old.height);
+        old.buffer = backing;
+        old.visible = false;
+
+        let new = &mut self.writers[id.0];
+        let screen = unsafe { screen() };
+        copy_buffer(new.buffer, screen, new.height);
+        new.buffer = screen;
+        new.visible = true;
+        new.show_cursor();
+        self.active = id;
+    }
+
+    /// Reserves the bottom row of the screen for [`Consoles::draw_status_bar`]
+    /// or gives it back to the consoles.
+    pub fn set_status_bar(&mut self, enabled: bool) {
+        if enabled == self.status_bar {
+            return;
+        }
+        self.status_bar = enabled;
+        let height = if enabled {
+            BUFFER_HEIGHT - 1
+        } else {
+            BUFFER_HEIGHT
+        };
+        for writer in &mut self.writers {
+            writer.set_height(height);
+        }
+        if enabled {
+            self.draw_status_bar(format_args!(""));
+        }
+    }
+
+    pub fn has_status_bar(&self) -> bool {
+        self.status_bar
+    }
+
+    /// Replaces the text of the status bar, cutting it off at the screen
+    /// width. Does nothing if the status bar is not enabled.
+    pub fn draw_status_bar(&mut self, args: fmt::Arguments) {
+        if !self.status_bar {
+            return;
+        }
+        let screen = &mut self.active_mut().buffer;
+        let mut bar = StatusBar {
+            row: &mut screen.chars[BUFFER_HEIGHT - 1],
+            col: 0,
+        };
+        let _ = fmt::Write::write_fmt(&mut bar, args);
+        let col = bar.col;
+        for cell in &mut bar.row[col..] {
+            cell.write(STATUS_BAR_BLANK);
+        }
+    }
+}
+
+const STATUS_BAR_BLANK: ScreenChar = ScreenChar {
+    ascii_char: b' ',
+    color: ColorCode::new(Color::Black, Color::LightGray),
+};
+
+/// Writes to the status bar row, dropping what does not fit.
+struct StatusBar<'a> {
+    row: &'a mut [Volatile<ScreenChar>; BUFFER_WIDTH],
+    col: usize,
+}
+
+impl fmt::Write for StatusBar<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.col == BUFFER_WIDTH {
+                break;
+            }
+            self.row[self.col].write(ScreenChar {
+                ascii_char: cp437::from_char(c).unwrap_or(0xfe),
+                ..STATUS_BAR_BLANK
+            });
+            self.col += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the top `rows` rows.
+fn copy_buffer(from: &Buffer, to: &mut Buffer, rows: usize) {
+    for (from_row, to_row) in from.chars.iter().zip(to.chars.iter_mut()).take(rows) {
+        for (from_char, to_char) in from_row.iter().zip(to_row.iter_mut()) {
+            to_char.write(from_char.read());
+        }
+    }
+}
+
+/// The console called `name`, see [`Consoles::named`].
+pub fn console(name: &'static str) -> Option<ConsoleId> {
+    CONSOLES.lock().named(name)
+}
+
+pub fn switch_console(id: ConsoleId) {
+    CONSOLES.lock().switch_to(id);
+}
+
+pub fn active_console() -> ConsoleId {
+    CONSOLES.lock().active()
+}
+
+/// Runs `f` with the writer of a console locked.
+pub fn with_console<R>(id: ConsoleId, f: impl FnOnce(&mut Writer) -> R) -> R {
+    f(CONSOLES.lock().get_mut(id))
+}
+
+/// Runs `f` with the writer of the shown console locked.
+pub fn with_active_console<R>(f: impl FnOnce(&mut Writer) -> R) -> R {
+    f(CONSOLES.lock().active_mut())
+}
+
+/// Prints to a virtual console, or to the framebuffer console once graphics
+/// mode is on, which shows the output of every virtual console.
+#[doc(hidden)]
+pub fn _console_print(id: ConsoleId, args: fmt::Arguments) {
+    use core::fmt::Write;
+    if crate::framebuffer::_print(args) {
+        return;
+    }
+    CONSOLES.lock().get_mut(id).write_fmt(args).unwrap();
+}
+
+/// Prints to a virtual console, given by its [`ConsoleId`]. In graphics mode
+/// this prints to the framebuffer console like [`print!`].
+#[macro_export]
+macro_rules! console_print {
+    ($console:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_console_print($console, format_args!($($arg)*))
+    );
+}
+
+/// Prints to a virtual console, given by its [`ConsoleId`], appending a
+/// newline.
+#[macro_export]
+macro_rules! console_println {
+    ($console:expr) => ($crate::console_print!($console, "\n"));
+    ($console:expr, $($arg:tt)*) => (
+        $crate::console_print!($console, "{}\n", format_args!($($arg)*))
+    );
+}
+
+#[test_case]
+fn test_switch_consoles() {
+    let mut consoles = CONSOLES.lock();
+    let other = consoles.named("test").unwrap();
+    assert_ne!(other, ConsoleId::MAIN);
+    assert_eq!(consoles.named("test"), Some(other));
+    assert_eq!(consoles.name(other), Some("test"));
+
+    consoles
+        .get_mut(ConsoleId::MAIN)
+        .write_string("\x0cmain console");
+    consoles.get_mut(other).write_string("\x0cother console");
+    // the character in the top left corner of the screen
+    let first_char = || char::from(unsafe { (0xb8000 as *const u8).read_volatile() });
+    assert_eq!(first_char(), 'm');
+
+    consoles.switch_to(other);
+    assert_eq!(consoles.active(), other);
+    assert_eq!(first_char(), 'o');
+    // writing to a hidden console does not touch the screen
+    consoles.get_mut(ConsoleId::MAIN).write_string("\rM");
+    assert_eq!(first_char(), 'o');
+
+    consoles.switch_to(ConsoleId::MAIN);
+    assert_eq!(first_char(), 'M');
+    consoles.get_mut(ConsoleId::MAIN).write_string("\x1b[25;1H");
+}
@@ -0,0 +1,100 @@
+//! Translation from Unicode to code page 437, the character set of the VGA
+//! text mode font.
+
+/// The glyphs of the control characters 0x01 to 0x1f.
+const CONTROL: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', '►', '◄', '↕', '‼',
+    '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// The glyph of 0x7f.
+const HOUSE: char = '⌂';
+
+/// The characters 0x80 to 0xff.
+const HIGH: [char; 128] = [
+    // accented Latin and currency
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    // shades and box drawing
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    // Greek and math
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Code points that look like a CP437 glyph without being the one in the
+/// table.
+const ALIASES: [(char, u8); 7] = [
+    // Greek small beta, drawn like sharp s
+    ('\u{3b2}', 0xe1),
+    // Greek small mu instead of the micro sign
+    ('\u{3bc}', 0xe6),
+    // ohm sign
+    ('\u{2126}', 0xea),
+    // n-ary summation
+    ('\u{2211}', 0xe4),
+    // element of
+    ('\u{2208}', 0xee),
+    // empty set
+    ('\u{2205}', 0xed),
+    // black small square
+    ('\u{25aa}', 0xfe),
+];
+
+/// The CP437 byte that displays `c`, `None` if there is none.
+///
+/// ASCII maps to itself, including control characters, which the writer
+/// handles before drawing anything.
+pub fn from_char(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+    if c == HOUSE {
+        return Some(0x7f);
+    }
+    let position = |table: &[char]| table.iter().position(|&entry| entry == c);
+    if let Some(index) = position(&HIGH) {
+        return Some(0x80 + index as u8);
+    }
+    if let Some(index) = position(&CONTROL) {
+        return Some(0x01 + index as u8);
+    }
+    ALIASES
+        .iter()
+        .find(|&&(alias, _)| alias == c)
+        .map(|&(_, byte)| byte)
+}
+
+/// The character CP437 byte `byte` displays as.
+pub fn to_char(byte: u8) -> char {
+    match byte {
+        0x00 => ' ',
+        0x01..=0x1f => CONTROL[usize::from(byte) - 1],
+        0x7f => HOUSE,
+        0x80..=0xff => HIGH[usize::from(byte) - 0x80],
+        _ => char::from(byte),
+    }
+}
+
+#[test_case]
+fn test_table_round_trip() {
+    for byte in 0x01..=0xff {
+        assert_eq!(from_char(to_char(byte)), Some(byte), "byte {:#x}", byte);
+    }
+}
+
+#[test_case]
+fn test_common_characters() {
+    assert_eq!(from_char('A'), Some(b'A'));
+    assert_eq!(from_char('é'), Some(0x82));
+    assert_eq!(from_char('╔'), Some(0xc9));
+    assert_eq!(from_char('π'), Some(0xe3));
+    assert_eq!(from_char('≤'), Some(0xf3));
+    assert_eq!(from_char('☺'), Some(0x01));
+    assert_eq!(from_char('\u{3bc}'), from_char('\u{b5}'));
+    assert_eq!(from_char('日'), None);
+    assert_eq!(from_char('€'), None);
+}
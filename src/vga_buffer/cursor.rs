@@ -0,0 +1,77 @@
+//! The hardware text cursor, drawn by the CRT controller.
+
+use x86_64::instructions::port::Port;
+
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+
+const CURSOR_START: u8 = 0x0a;
+const CURSOR_END: u8 = 0x0b;
+const CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+/// Bit of the cursor start register that turns the cursor off.
+const CURSOR_DISABLE: u8 = 1 << 5;
+/// Last scan line of a character cell in the 80x25 text mode.
+const LAST_SCAN_LINE: u8 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Hidden,
+    Underline,
+    HalfBlock,
+    Block,
+}
+
+impl CursorShape {
+    /// First and last scan line of the cursor in a character cell.
+    fn scan_lines(self) -> (u8, u8) {
+        match self {
+            CursorShape::Hidden | CursorShape::Underline => (LAST_SCAN_LINE - 1, LAST_SCAN_LINE),
+            CursorShape::HalfBlock => (LAST_SCAN_LINE / 2 + 1, LAST_SCAN_LINE),
+            CursorShape::Block => (0, LAST_SCAN_LINE),
+        }
+    }
+}
+
+fn read_register(index: u8) -> u8 {
+    unsafe {
+        Port::new(CRTC_INDEX).write(index);
+        Port::new(CRTC_DATA).read()
+    }
+}
+
+fn write_register(index: u8, value: u8) {
+    unsafe {
+        Port::new(CRTC_INDEX).write(index);
+        Port::new(CRTC_DATA).write(value);
+    }
+}
+
+/// Sets the scan lines of the cursor, keeping the reserved upper bits of the
+/// registers.
+pub(super) fn set_shape(shape: CursorShape) {
+    let (start, end) = shape.scan_lines();
+    let disable = if shape == CursorShape::Hidden {
+        CURSOR_DISABLE
+    } else {
+        0
+    };
+    write_register(
+        CURSOR_START,
+        (read_register(CURSOR_START) & 0xc0) | disable | start,
+    );
+    write_register(CURSOR_END, (read_register(CURSOR_END) & 0xe0) | end);
+}
+
+/// Moves the cursor to a cell, counted row by row from the top left.
+pub(super) fn set_position(cell: u16) {
+    write_register(CURSOR_LOCATION_LOW, cell as u8);
+    write_register(CURSOR_LOCATION_HIGH, (cell >> 8) as u8);
+}
+
+#[cfg(test)]
+pub(super) fn position() -> u16 {
+    u16::from(read_register(CURSOR_LOCATION_HIGH)) << 8
+        | u16::from(read_register(CURSOR_LOCATION_LOW))
+}
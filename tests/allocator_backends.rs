@@ -0,0 +1,193 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::allocator::bump::BumpAllocator;
+use blog_os::allocator::fixed_size_block::FixedSizeBlockAllocator;
+use blog_os::allocator::linked_list::LinkedListAllocator;
+use blog_os::allocator::{self, HeapAllocator};
+use blog_os::timer::Instant;
+use blog_os::{memory, paging, serial_println};
+use bootloader::{entry_point, BootInfo};
+use core::alloc::Layout;
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+/// A region separate from the kernel heap that each backend is tested on.
+const TEST_HEAP_START: usize = 0x_6666_0000_0000;
+const TEST_HEAP_SIZE: usize = 64 * 1024;
+
+const BENCHMARK_ROUNDS: usize = 100_000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe {
+        memory::init(&boot_info.memory_map);
+        paging::init(VirtAddr::new(boot_info.physical_memory_offset));
+    }
+    allocator::init_heap().expect("heap initialization failed");
+    allocator::map_region(TEST_HEAP_START, TEST_HEAP_SIZE).expect("mapping test heap failed");
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn new_heap<A: HeapAllocator + Default>() -> A {
+    let mut heap = A::default();
+    unsafe { heap.init(TEST_HEAP_START, TEST_HEAP_SIZE) };
+    heap
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+fn allocate_filled<A: HeapAllocator>(heap: &mut A, size: usize, value: u8) -> *mut u8 {
+    let ptr = heap.allocate(layout(size));
+    assert!(!ptr.is_null(), "allocation of {} bytes failed", size);
+    unsafe { ptr.write_bytes(value, size) };
+    ptr
+}
+
+fn check_filled(ptr: *mut u8, size: usize, value: u8) {
+    for i in 0..size {
+        assert_eq!(unsafe { ptr.add(i).read() }, value);
+    }
+}
+
+/// Allocations do not overlap and their contents survive other allocations.
+fn distinct_allocations<A: HeapAllocator + Default>() {
+    let mut heap = new_heap::<A>();
+    let mut live = [core::ptr::null_mut(); 16];
+    for (i, ptr) in live.iter_mut().enumerate() {
+        *ptr = allocate_filled(&mut heap, 16 + i * 24, i as u8);
+    }
+    for (i, &ptr) in live.iter().enumerate() {
+        check_filled(ptr, 16 + i * 24, i as u8);
+    }
+    for (i, &ptr) in live.iter().enumerate().rev() {
+        unsafe { heap.deallocate(ptr, layout(16 + i * 24)) };
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+/// Memory is reused when allocations are freed right away.
+fn many_short_lived<A: HeapAllocator + Default>() {
+    let mut heap = new_heap::<A>();
+    for i in 0..TEST_HEAP_SIZE {
+        let size = 8 << (i % 8);
+        let ptr = allocate_filled(&mut heap, size, i as u8);
+        unsafe { heap.deallocate(ptr, layout(size)) };
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+/// A long-lived allocation does not prevent reuse of the memory after it.
+fn many_with_long_lived<A: HeapAllocator + Default>() {
+    let mut heap = new_heap::<A>();
+    let long_lived = allocate_filled(&mut heap, 64, 0xaa);
+    for i in 0..TEST_HEAP_SIZE {
+        let ptr = allocate_filled(&mut heap, 32, i as u8);
+        unsafe { heap.deallocate(ptr, layout(32)) };
+    }
+    check_filled(long_lived, 64, 0xaa);
+    unsafe { heap.deallocate(long_lived, layout(64)) };
+    assert_eq!(heap.used(), 0);
+}
+
+/// Large allocations can be repeated after being freed.
+fn large_allocations<A: HeapAllocator + Default>() {
+    let mut heap = new_heap::<A>();
+    let size = TEST_HEAP_SIZE / 2;
+    for i in 0..10 {
+        let ptr = allocate_filled(&mut heap, size, i);
+        check_filled(ptr, size, i);
+        unsafe { heap.deallocate(ptr, layout(size)) };
+    }
+}
+
+fn alignment<A: HeapAllocator + Default>() {
+    let mut heap = new_heap::<A>();
+    for &align in &[8, 64, 512, 4096] {
+        let layout = Layout::from_size_align(24, align).unwrap();
+        let ptr = heap.allocate(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % align, 0);
+        unsafe { heap.deallocate(ptr, layout) };
+    }
+}
+
+fn exhaustion<A: HeapAllocator + Default>() {
+    let mut heap = new_heap::<A>();
+    assert!(heap.allocate(layout(TEST_HEAP_SIZE + 1)).is_null());
+    assert!(heap.allocate(layout(isize::MAX as usize - 64)).is_null());
+    // the heap is still usable afterwards
+    let ptr = allocate_filled(&mut heap, 128, 1);
+    unsafe { heap.deallocate(ptr, layout(128)) };
+}
+
+fn run_suite<A: HeapAllocator + Default>() {
+    distinct_allocations::<A>();
+    many_short_lived::<A>();
+    many_with_long_lived::<A>();
+    large_allocations::<A>();
+    alignment::<A>();
+    exhaustion::<A>();
+}
+
+/// Measures allocations in batches of mixed sizes, each batch freed in
+/// reverse order, and prints the throughput to serial.
+fn benchmark<A: HeapAllocator + Default>(name: &str) {
+    let mut heap = new_heap::<A>();
+    let mut batch: [*mut u8; 8] = [core::ptr::null_mut(); 8];
+    let start = Instant::now();
+    for _ in 0..BENCHMARK_ROUNDS / batch.len() {
+        for (i, ptr) in batch.iter_mut().enumerate() {
+            *ptr = heap.allocate(layout(8 << i));
+            assert!(!ptr.is_null());
+        }
+        for (i, &ptr) in batch.iter().enumerate().rev() {
+            unsafe { heap.deallocate(ptr, layout(8 << i)) };
+        }
+    }
+    let elapsed = start.elapsed();
+    let micros = elapsed.as_micros().max(1);
+    serial_println!(
+        "\n  {}: {} allocations in {} ms ({} allocations/s)",
+        name,
+        BENCHMARK_ROUNDS,
+        elapsed.as_millis(),
+        BENCHMARK_ROUNDS as u128 * 1_000_000 / micros
+    );
+}
+
+#[test_case]
+fn bump_allocator() {
+    run_suite::<BumpAllocator>();
+}
+
+#[test_case]
+fn linked_list_allocator() {
+    run_suite::<LinkedListAllocator>();
+}
+
+#[test_case]
+fn fixed_size_block_allocator() {
+    run_suite::<FixedSizeBlockAllocator>();
+}
+
+#[test_case]
+fn benchmark_throughput() {
+    benchmark::<BumpAllocator>("bump");
+    benchmark::<LinkedListAllocator>("linked list");
+    benchmark::<FixedSizeBlockAllocator>("fixed-size block");
+}
@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::framebuffer::{self, FramebufferError, Rect, Rgb};
+use blog_os::vga_buffer::{self, Color, ConsoleId};
+use blog_os::{console_print, memory, paging, println, status_bar};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe {
+        memory::init(&boot_info.memory_map);
+        paging::init(VirtAddr::new(boot_info.physical_memory_offset));
+    }
+    framebuffer::init(640, 480).expect("switching to graphics mode failed");
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn println_draws_on_framebuffer() {
+    println!("\x0c\x1b[32m|");
+    framebuffer::with_console(|console| {
+        assert_eq!(console.size(), (60, 80));
+        let framebuffer = console.framebuffer_mut();
+        // the bar of '|' is in the fourth and fifth column of the glyph
+        assert_eq!(framebuffer.pixel(3, 0), Some(Rgb::from(Color::Green)));
+        assert_eq!(framebuffer.pixel(2, 0), Some(Rgb::BLACK));
+        console.write_string("\x1b[0m");
+    })
+    .expect("no framebuffer console");
+}
+
+#[test_case]
+fn console_output_goes_to_framebuffer() {
+    console_print!(ConsoleId::new(1).unwrap(), "\x0cab");
+    framebuffer::with_console(|console| assert_eq!(console.cursor_position(), (0, 2)))
+        .expect("no framebuffer console");
+    // text mode only, so this must not draw on the VGA buffer
+    status_bar::enable();
+    assert!(!vga_buffer::CONSOLES.lock().has_status_bar());
+}
+
+#[test_case]
+fn draw_on_framebuffer() {
+    framebuffer::with_console(|console| {
+        let framebuffer = console.framebuffer_mut();
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        let blue = Rgb::new(0x20, 0x40, 0xff);
+        framebuffer.fill_rect(Rect::new(width - 10, height - 10, 20, 20), blue);
+        assert_eq!(framebuffer.pixel(width - 1, height - 1), Some(blue));
+        framebuffer.draw_line((0, 0), (width as i32, height as i32), Rgb::WHITE);
+        assert_eq!(framebuffer.pixel(0, 0), Some(Rgb::WHITE));
+    })
+    .expect("no framebuffer console");
+}
+
+#[test_case]
+fn init_only_once() {
+    assert!(matches!(
+        framebuffer::init(640, 480),
+        Err(FramebufferError::AlreadyInitialized)
+    ));
+}
@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::allocator::{self, HEAP_SIZE};
+use blog_os::{memory, paging};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe {
+        memory::init(&boot_info.memory_map);
+        paging::init(VirtAddr::new(boot_info.physical_memory_offset));
+    }
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn large_allocation() {
+    // half of the initial heap in a single allocation
+    let size = HEAP_SIZE / 2;
+    let mut buffer = Vec::<u8>::with_capacity(size);
+    buffer.resize(size, 0xab);
+    assert!(buffer.iter().all(|&byte| byte == 0xab));
+}
+
+#[test_case]
+fn many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn many_boxes_long_lived() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn reuse_after_free() {
+    let (used_before, _) = allocator::heap_usage();
+    for _ in 0..100 {
+        let buffer = Vec::<u8>::with_capacity(HEAP_SIZE / 4);
+        drop(buffer);
+    }
+    assert_eq!(allocator::heap_usage().0, used_before);
+}
+
+#[test_case]
+fn grow_past_initial_size() {
+    let size = HEAP_SIZE * 2;
+    let mut buffer = Vec::<u8>::with_capacity(size);
+    buffer.resize(size, 0xcd);
+    assert!(buffer.iter().all(|&byte| byte == 0xcd));
+    let (used, free) = allocator::heap_usage();
+    assert!(used + free > HEAP_SIZE);
+}
+
+#[test_case]
+fn growth_stops_at_limit() {
+    let limit = allocator::heap_limit();
+    let (used, free) = allocator::heap_usage();
+    allocator::set_heap_limit(used + free + HEAP_SIZE);
+    let mut buffer = Vec::<u8>::new();
+    assert!(buffer.try_reserve_exact(used + free + HEAP_SIZE).is_err());
+    allocator::set_heap_limit(limit);
+}
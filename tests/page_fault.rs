@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+use blog_os::page_fault::{self, PageFault};
+use blog_os::{exit_qemu, memory, paging, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+const FAULT_ADDRESS: u64 = 0xdeadbeef;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("page_fault::recovery_hook...\t");
+
+    blog_os::init();
+    unsafe {
+        memory::init(&boot_info.memory_map);
+        paging::init(VirtAddr::new(boot_info.physical_memory_offset));
+    }
+    page_fault::register_region(VirtAddr::new(0xdeadb000), 0x1000, recovery_hook)
+        .expect("failed to register fault region");
+
+    // trigger a page fault that the hook resolves by mapping the page
+    let ptr = FAULT_ADDRESS as *mut u8;
+    let value = unsafe {
+        ptr.write_volatile(42);
+        ptr.read_volatile()
+    };
+
+    if value != 42 {
+        serial_println!("[failed]\n\nread back {} after the retried write", value);
+        exit_qemu(QemuExitCode::Failed);
+    } else {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    }
+    blog_os::hlt_loop();
+}
+
+fn recovery_hook(fault: &PageFault) -> bool {
+    if fault.address.as_u64() != FAULT_ADDRESS || !fault.is_write() || fault.is_present() {
+        serial_println!("[failed]\n\nunexpected fault:\n{}", fault);
+        exit_qemu(QemuExitCode::Failed);
+        blog_os::hlt_loop();
+    }
+    let page = Page::containing_address(fault.address);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    paging::map_new_page(page, flags).is_ok()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
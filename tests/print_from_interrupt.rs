@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::{println, serial_println, timer};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+static HOOK_PRINTS: AtomicUsize = AtomicUsize::new(0);
+
+fn print_on_tick() {
+    let count = HOOK_PRINTS.fetch_add(1, Ordering::SeqCst);
+    println!("tick {}", count);
+}
+
+/// Before printing was interrupt-safe, a tick that printed while the main code
+/// held the writer lock deadlocked.
+#[test_case]
+fn print_from_timer_interrupt() {
+    timer::set_tick_hook(Some(print_on_tick));
+    let start = timer::ticks();
+    let mut lines = 0;
+    // keep printing until plenty of ticks printed in between
+    while HOOK_PRINTS.load(Ordering::SeqCst) < 50 || timer::ticks() - start < 50 {
+        println!("main loop line {}", lines);
+        lines += 1;
+    }
+    timer::set_tick_hook(None);
+    let ticks = HOOK_PRINTS.load(Ordering::SeqCst);
+    assert!(lines > 0);
+    assert!(ticks >= 50);
+    serial_println!("{} main loop lines, {} tick lines", lines, ticks);
+}